@@ -0,0 +1,161 @@
+extern crate gbrust;
+
+use std::env;
+use std::cmp;
+use std::fs::File;
+use std::io::prelude::*;
+
+use gbrust::cpu::Condition;
+use gbrust::instruction::Instruction;
+use gbrust::opcode::Opcode;
+
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() <= 1 {
+        fail(Failure::NotEnoughArgs);
+    }
+    if !args[1].contains(".bin") {
+        fail(Failure::ArgumentNotBinary);
+    }
+    let filename = &args[1];
+    let file = File::open(filename);
+    let mut handle = match file {
+        Ok(result) => result,
+        Err(_) => fail(Failure::FileReadError(filename)),
+    };
+    let mut rom = Vec::new();
+    if handle.read_to_end(&mut rom).is_err() {
+        fail(Failure::FileReadError(filename));
+    }
+    dissasm(&rom);
+}
+
+struct Disassembly<'a> {
+    rom: &'a [u8],
+    bytes: usize,
+    data_ends: usize,
+    calls: Vec<usize>,
+    jumps_forward: Vec<usize>,
+}
+
+impl<'a> Disassembly<'a> {
+    pub fn new(rom: &'a [u8]) -> Self {
+        Disassembly {
+            rom,
+            bytes: 0,
+            data_ends: 0,
+            calls: Vec::new(),
+            jumps_forward: Vec::new(),
+        }
+    }
+    fn check_if_data_segment(&mut self) {
+        let n = self.jumps_forward
+            .iter()
+            .cloned()
+            .filter(|n| n > &self.bytes)
+            .min()
+            .unwrap_or(0);
+        let m = self.calls
+            .iter()
+            .cloned()
+            .filter(|n| n > &self.bytes)
+            .min()
+            .unwrap_or(0);
+        // The data will end at the minimal jumped address
+        // If there is no minimal jumped address, data_end will be
+        // 0 so that the data check always fails.
+        self.data_ends = if n == 0 {
+            m
+        } else if m == 0 {
+            n
+        } else {
+            cmp::min(n, m)
+        };
+    }
+    pub fn parse_line(&mut self) {
+        let is_data = self.bytes < self.data_ends;
+        if !is_data {
+            self.data_ends = 0;
+        }
+        match Opcode::lookup(&self.rom[self.bytes..]) {
+            Some(row) if !is_data => self.parse_instruction(row),
+            // An instruction cut off by the end of the file can only be data
+            _ => self.parse_data(),
+        }
+    }
+    fn parse_data(&mut self) {
+        // Break up data in 16 byte chunks
+        let end = cmp::max(self.data_ends, self.bytes + 1);
+        let end = cmp::min(cmp::min(end, self.bytes + 16), self.rom.len());
+        let data: Vec<String> = self.rom[self.bytes..end]
+            .iter()
+            .map(|byte| format!("${:02X}", byte))
+            .collect();
+        println!("{:04X}: .db {}", self.bytes, data.join(", "));
+        self.bytes = end;
+    }
+    fn parse_instruction(&mut self, row: &Opcode) {
+        let bytes = &self.rom[self.bytes..self.bytes + row.length as usize];
+        match row.instruction(bytes) {
+            Instruction::Ret(Condition::Always) => {
+                // Returns clear the calls that have already been passed
+                let passed = self.bytes;
+                self.calls.retain(|&n| n > passed);
+                self.check_if_data_segment();
+            }
+            Instruction::Jr(condition, offset) => {
+                // The offset counts from the end of the 2 byte instruction
+                let offset = offset as isize + 2;
+                let address = (self.bytes as isize + offset) as usize;
+                if offset > 0 {
+                    // Add forward conditional jumps
+                    if condition != Condition::Always {
+                        self.jumps_forward.push(address);
+                    }
+                } else if offset < 0 {
+                    // If we see a non-conditional backward jump, we may be starting
+                    // a data segment
+                    if condition == Condition::Always {
+                        self.check_if_data_segment();
+                    }
+                }
+            }
+            Instruction::Call(_, address) if address as usize > self.bytes => {
+                self.calls.push(address as usize);
+            }
+            Instruction::Jp(Condition::Always, address) => {
+                self.data_ends = address as usize;
+            }
+            _ => {}
+        }
+        let line = row.disassemble(bytes, self.bytes as u16);
+        println!("{:04X}: {: <15}x{:02X}", self.bytes, line, bytes[0]);
+        self.bytes += bytes.len();
+    }
+}
+
+fn dissasm(rom: &[u8]) {
+    let disassembly = &mut Disassembly::new(rom);
+    while disassembly.bytes < rom.len() {
+        disassembly.parse_line();
+    }
+}
+
+fn fail(error: Failure) -> ! {
+    use Failure::*;
+    let err = match error {
+        NotEnoughArgs => String::from("Not enough arguments"),
+        ArgumentNotBinary => String::from("Input must be a binary file .bin"),
+        FileReadError(file) => format!("Not able to read input file {}", file),
+    };
+    println!("ERR: {}\n", err);
+    println!("Usage: gd_dasm FILE");
+    println!("Where FILE is any binary GB file");
+    std::process::exit(1);
+}
+
+enum Failure<'a> {
+    NotEnoughArgs,
+    ArgumentNotBinary,
+    FileReadError(&'a str),
+}
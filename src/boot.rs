@@ -0,0 +1,178 @@
+use std::fmt;
+
+use model::Model;
+
+/// Size of a DMG, MGB or SGB boot ROM, which maps over 0x0000-0x00FF
+pub const BOOT_ROM_SIZE: usize = 0x100;
+
+/// SHA-1 of every known boot ROM, along with the model it boots
+static KNOWN_BOOT_ROMS: [(&str, Model); 5] = [
+    ("8bd501e31921e9601788316dbd3ce9833a97bcbc", Model::Dmg0),
+    ("4ed31ec6b0b175bb109c0eb5fd3d193da823339f", Model::Dmg),
+    ("4e68f9da03c310e84c523654b9026e51f26ce7f0", Model::Mgb),
+    ("aa2f50a77dfb4823da96ba99309085a3c6278515", Model::Sgb),
+    // The SGB2 boot ROM, which leaves the machine just like the SGB one
+    ("93407ea10d2f30ab96a314d8eca44fe160aea734", Model::Sgb),
+];
+
+/// A verified boot ROM, mapped over the start of the cartridge until FF50 is written
+#[derive(Clone)]
+pub struct BootRom {
+    bytes: [u8; BOOT_ROM_SIZE],
+    model: Model,
+}
+
+/// Why a boot ROM image was rejected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BootRomError {
+    /// The image is not 256 bytes long. Holds the size it was.
+    Size(usize),
+    /// The image is the right size but matches no known boot ROM. Holds its SHA-1.
+    Unknown(String),
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BootRomError::Size(size) => {
+                write!(f, "Boot ROM is {} bytes, expected {}", size, BOOT_ROM_SIZE)
+            }
+            BootRomError::Unknown(ref hash) => write!(f, "Unknown boot ROM with SHA-1 {}", hash),
+        }
+    }
+}
+
+impl BootRom {
+    /// USAGE: BootRom::new(BYTES) where BYTES is a whole boot ROM image, e.g. DMG_ROM.bin
+    /// Returns the boot ROM if BYTES is one of the known boot ROMs, byte for byte
+    pub fn new(bytes: &[u8]) -> Result<BootRom, BootRomError> {
+        if bytes.len() != BOOT_ROM_SIZE {
+            return Err(BootRomError::Size(bytes.len()));
+        }
+        let hash = hex(&sha1(bytes));
+        let model = match KNOWN_BOOT_ROMS.iter().find(|&&(known, _)| known == hash) {
+            Some(&(_, model)) => model,
+            None => return Err(BootRomError::Unknown(hash)),
+        };
+        let mut boot = BootRom {
+            bytes: [0; BOOT_ROM_SIZE],
+            model,
+        };
+        boot.bytes.copy_from_slice(bytes);
+        Ok(boot)
+    }
+    /// USAGE: boot.model()
+    /// Returns the model this boot ROM belongs to
+    pub fn model(&self) -> Model {
+        self.model
+    }
+    /// USAGE: boot.read(ADDR) where ADDR is in 0x0000-0x00FF
+    pub fn read(&self, address: u16) -> u8 {
+        self.bytes[address as usize]
+    }
+}
+
+/// Returns the lowercase hex digits of BYTES
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns the SHA-1 digest of BYTES, as in FIPS 180-4
+/// Only used to recognize boot ROMs, which is all SHA-1 is still good for
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    // Pad with a 1 bit, zeroes up to 56 mod 64 bytes, then the length in bits
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0x00);
+    }
+    let bits = (bytes.len() as u64).wrapping_mul(8);
+    for i in (0..8).rev() {
+        message.push((bits >> (i * 8)) as u8);
+    }
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            let word = &chunk[i * 4..i * 4 + 4];
+            w[i] = u32::from(word[0]) << 24
+                | u32::from(word[1]) << 16
+                | u32::from(word[2]) << 8
+                | u32::from(word[3]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (i, state) in h.iter().enumerate() {
+        for j in 0..4 {
+            digest[i * 4 + j] = (state >> (24 - j * 8)) as u8;
+        }
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Checks the digest against the test vectors of FIPS 180
+    #[test]
+    fn can_hash_with_sha1() {
+        let vectors = [
+            ("", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            ("abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            ),
+        ];
+        for &(message, digest) in vectors.iter() {
+            assert_eq!(hex(&sha1(message.as_bytes())), digest);
+        }
+        let million = vec![b'a'; 1_000_000];
+        assert_eq!(hex(&sha1(&million)), "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+    }
+    // Checks that the bundled DMG boot ROM is recognized and anything else is not
+    #[test]
+    fn can_verify_boot_roms() {
+        let bytes = include_bytes!("../DMG_ROM.bin");
+        let boot = BootRom::new(bytes).unwrap();
+        assert_eq!(boot.model(), Model::Dmg);
+        // The boot ROM starts with LD SP,$FFFE and ends by writing to FF50
+        assert_eq!((boot.read(0x0000), boot.read(0x0001), boot.read(0x0002)), (0x31, 0xFE, 0xFF));
+        assert_eq!((boot.read(0x00FE), boot.read(0x00FF)), (0xE0, 0x50));
+        assert_eq!(BootRom::new(&bytes[..0xFF]).err(), Some(BootRomError::Size(0xFF)));
+        let mut patched = bytes.to_vec();
+        patched[0x00FF] ^= 0x01;
+        match BootRom::new(&patched) {
+            Err(BootRomError::Unknown(hash)) => assert_eq!(hash.len(), 40),
+            _ => panic!("Patched boot ROM was accepted"),
+        }
+        assert_eq!(BootRomError::Size(0).to_string(), "Boot ROM is 0 bytes, expected 256");
+    }
+}
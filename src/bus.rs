@@ -0,0 +1,41 @@
+/// Anything the CPU can read and write bytes through
+/// Addresses cover the full 16-bit address space of the Game Boy
+pub trait Bus {
+    /// USAGE: bus.read(ADDR) where ADDR is the address to read
+    /// Returns the byte currently visible at ADDR
+    fn read(&mut self, address: u16) -> u8;
+    /// USAGE: bus.write(ADDR, N) where ADDR is the address to write and N the byte to write
+    fn write(&mut self, address: u16, value: u8);
+    /// USAGE: bus.tick()
+    /// Advances everything attached to the bus (timer, PPU, DMA) by one m-cycle
+    /// The CPU calls this once per m-cycle it spends, right before that cycle's access if any,
+    /// so an access made mid-instruction sees the hardware exactly as it is at that point
+    fn tick(&mut self) {}
+    /// USAGE: bus.bank(ADDR)
+    /// Returns which bank is mapped at ADDR, for anything that caches code by address
+    /// A bus that switches banks or overlays memory must return a different number for each
+    /// thing it can map at ADDR, and may only switch when written. Whatever shares a bank
+    /// number within one 4 KiB window, e.g. 0x3000-0x3FFF, must switch together. A flat address
+    /// space only ever has bank 0.
+    fn bank(&self, _address: u16) -> u16 {
+        0
+    }
+    /// USAGE: bus.mirror(ADDR)
+    /// Returns the first address that reaches the same memory as ADDR, e.g. 0xC000 for 0xE000
+    /// in echo RAM, so anything that caches code by address sees writes through any mirror
+    /// Without mirrors, every address is its own.
+    fn mirror(&self, address: u16) -> u16 {
+        address
+    }
+}
+
+/// A flat 64 KiB address space with nothing mapped into it
+/// Useful for running the CPU on its own, e.g. in tests
+impl Bus for [u8; 0x10000] {
+    fn read(&mut self, address: u16) -> u8 {
+        self[address as usize]
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        self[address as usize] = value;
+    }
+}
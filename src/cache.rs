@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+use bus::Bus;
+use cartridge::NO_BANK;
+use instruction::Instruction;
+use opcode::OPCODES;
+
+/// Longest run of instructions kept in one block
+const MAX_BLOCK: usize = 64;
+/// Most blocks kept at once, after which decoding another evicts the oldest
+/// Far more than the code a game runs in any one stretch, even across every bank.
+const MAX_BLOCKS: usize = 0x4000;
+/// Bytes in each page of memory the cache tracks blocks by, to find the ones a write hits
+const PAGE_SIZE: usize = 0x100;
+/// Blocks never cross a multiple of this, the smallest window anything banks memory in
+/// Checking the bank at PC then checks it for the whole block.
+const WINDOW: u16 = 0x1000;
+
+/// Decoded basic blocks, keyed by the bank and address they start at
+/// A block runs up to and including the first instruction that can change PC other than
+/// by falling through, or stops early at the edge of cacheable memory or of a bank window.
+/// A write drops just the blocks decoded from the memory written, through whichever mirror
+/// of it, so self-modifying code is always decoded afresh while the rest of the cache stays.
+pub struct BlockCache {
+    /// Every block, by slot. Dropped blocks leave their slot empty for the next one.
+    blocks: Vec<Option<Block>>,
+    free: Vec<usize>,
+    // The slot evicted next once every slot is taken
+    hand: usize,
+    index: HashMap<(u16, u16), usize, BuildHasherDefault<KeyHasher>>,
+    // The slots of the blocks with code in each page
+    pages: Vec<Vec<usize>>,
+    // The block and instruction the previous lookup ended at, so running straight through a
+    // block needs no hashing
+    cursor: Option<(usize, usize)>,
+    // Counts writes and dropped blocks. Banks only change on writes, so while this stays the
+    // same, neither the bank at PC nor the block that followed another last time can change.
+    epoch: u64,
+    // The epoch the bank at PC was last looked up in
+    checked: u64,
+}
+
+struct Block {
+    bank: u16,
+    /// The address the block starts at
+    pc: u16,
+    /// The first and last address of the memory the block was decoded from, as bus.mirror()
+    /// has them
+    start: u16,
+    end: u16,
+    /// Address, decoded instruction and length of each instruction in the block
+    instructions: Vec<(u16, Instruction, u16)>,
+    /// Where execution went after the block last time, the slot of the block there and the
+    /// epoch that was in
+    link: Option<(u16, usize, u64)>,
+}
+
+/// Hashes the (bank, address) keys of the index, which need nothing like SipHash
+#[derive(Default)]
+struct KeyHasher(u64);
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        // Spread the bits, since the table picks buckets with the low ones and tags with
+        // the high ones
+        self.0.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = self.0 << 8 | u64::from(byte);
+        }
+    }
+    fn write_u16(&mut self, n: u16) {
+        self.0 = self.0 << 16 | u64::from(n);
+    }
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        BlockCache {
+            blocks: Vec::new(),
+            free: Vec::new(),
+            hand: 0,
+            index: HashMap::default(),
+            pages: vec![Vec::new(); 0x10000 / PAGE_SIZE],
+            cursor: None,
+            epoch: 1,
+            checked: 0,
+        }
+    }
+    /// USAGE: cache.fetch(BUS, PC)
+    /// Returns the decoded instruction at PC and its length, decoding the block starting at
+    /// PC first if needed. Returns None if PC is outside cacheable memory or in no bank.
+    pub fn fetch<B: Bus>(&mut self, bus: &mut B, pc: u16) -> Option<(Instruction, u16)> {
+        let previous = self.cursor.take();
+        if let Some((slot, next)) = previous {
+            // The cursor is dropped along with its block, so the block is always there
+            let block = self.blocks[slot].as_ref().unwrap();
+            if let Some(&(address, instruction, length)) = block.instructions.get(next) {
+                let checked = self.checked == self.epoch;
+                if address == pc && (checked || block.bank == bus.bank(pc)) {
+                    self.checked = self.epoch;
+                    self.cursor = Some((slot, next + 1));
+                    return Some((instruction, length));
+                }
+            }
+            if let Some((to, link, epoch)) = block.link {
+                if to == pc && epoch == self.epoch {
+                    return Some(self.enter(link));
+                }
+            }
+        }
+        let bank = bus.bank(pc);
+        self.checked = self.epoch;
+        let slot = match self.index.get(&(bank, pc)) {
+            Some(&slot) => slot,
+            None => {
+                let block = self.decode(bus, bank, pc)?;
+                self.insert(block)
+            }
+        };
+        if let Some((from, _)) = previous {
+            // Decoding may have evicted the block execution came from
+            if let Some(ref mut block) = self.blocks[from] {
+                block.link = Some((pc, slot, self.epoch));
+            }
+        }
+        Some(self.enter(slot))
+    }
+    /// Moves the cursor to the start of the block in SLOT
+    /// Returns its first instruction and that instruction's length
+    fn enter(&mut self, slot: usize) -> (Instruction, u16) {
+        self.cursor = Some((slot, 1));
+        let (_, instruction, length) = self.blocks[slot].as_ref().unwrap().instructions[0];
+        (instruction, length)
+    }
+    /// USAGE: cache.write(ADDR) where ADDR is as bus.mirror() has it
+    /// Tells the cache ADDR was written, dropping every block decoded from it
+    pub fn write(&mut self, address: u16) {
+        self.epoch += 1;
+        let page = address as usize / PAGE_SIZE;
+        if self.pages[page].is_empty() {
+            return;
+        }
+        let hit: Vec<usize> = self.pages[page]
+            .iter()
+            .cloned()
+            .filter(|&slot| match self.blocks[slot] {
+                Some(ref block) => block.start <= address && address <= block.end,
+                None => false,
+            })
+            .collect();
+        for slot in hit {
+            self.remove(slot);
+        }
+    }
+    /// USAGE: cache.clear()
+    /// Drops every decoded block
+    pub fn clear(&mut self) {
+        for page in self.pages.iter_mut() {
+            page.clear();
+        }
+        self.blocks.clear();
+        self.free.clear();
+        self.hand = 0;
+        self.index.clear();
+        self.cursor = None;
+        self.epoch += 1;
+    }
+    /// Stores BLOCK, evicting the oldest block if the cache is full
+    /// Returns the slot it went in
+    fn insert(&mut self, block: Block) -> usize {
+        if self.free.is_empty() && self.blocks.len() == MAX_BLOCKS {
+            let victim = self.hand;
+            self.hand = (self.hand + 1) % MAX_BLOCKS;
+            self.remove(victim);
+        }
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.blocks.push(None);
+                self.blocks.len() - 1
+            }
+        };
+        for page in block.start as usize / PAGE_SIZE..=block.end as usize / PAGE_SIZE {
+            self.pages[page].push(slot);
+        }
+        self.index.insert((block.bank, block.pc), slot);
+        self.blocks[slot] = Some(block);
+        slot
+    }
+    /// Drops the block in SLOT, freeing the slot
+    fn remove(&mut self, slot: usize) {
+        let block = match self.blocks[slot].take() {
+            Some(block) => block,
+            None => return,
+        };
+        for page in block.start as usize / PAGE_SIZE..=block.end as usize / PAGE_SIZE {
+            self.pages[page].retain(|&other| other != slot);
+        }
+        self.index.remove(&(block.bank, block.pc));
+        self.free.push(slot);
+        self.epoch += 1;
+        if let Some((cursor, _)) = self.cursor {
+            if cursor == slot {
+                self.cursor = None;
+            }
+        }
+    }
+    /// Decodes the block starting at START in BANK, reading memory without ticking the bus
+    fn decode<B: Bus>(&mut self, bus: &mut B, bank: u16, start: u16) -> Option<Block> {
+        // Whatever is there, e.g. an MBC3 clock register, can change under the same address
+        // without a bank switch or a write the cache sees
+        if bank == NO_BANK {
+            return None;
+        }
+        let mirror = bus.mirror(start);
+        // Every byte must be in the same window and bank as START, and come straight after
+        // the one before in the memory it mirrors
+        let follows = |bus: &B, at: u16| {
+            (at == start || at & (WINDOW - 1) != 0)
+                && cacheable(at)
+                && bus.bank(at) == bank
+                && bus.mirror(at) == mirror.wrapping_add(at.wrapping_sub(start))
+        };
+        let mut instructions = Vec::new();
+        let mut address = start;
+        'block: while instructions.len() < MAX_BLOCK {
+            if !follows(bus, address) {
+                break;
+            }
+            let mut bytes = [bus.read(address), 0, 0];
+            let length = OPCODES[bytes[0] as usize].length as u16;
+            for i in 1..length {
+                let at = address.wrapping_add(i);
+                if !follows(bus, at) {
+                    break 'block;
+                }
+                bytes[i as usize] = bus.read(at);
+            }
+            let instruction = Instruction::decode(&bytes).unwrap();
+            instructions.push((address, instruction, length));
+            address = address.wrapping_add(length);
+            if ends_block(instruction) {
+                break;
+            }
+        }
+        let end = match instructions.last() {
+            Some(&(last, _, length)) => last + (length - 1),
+            None => return None,
+        };
+        Some(Block {
+            bank,
+            pc: start,
+            start: mirror,
+            end: mirror + (end - start),
+            instructions,
+            link: None,
+        })
+    }
+}
+
+/// Returns whether code at ADDRESS can be cached: ROM, cartridge RAM, WRAM and HRAM
+/// VRAM and OAM reads depend on the PPU, echo RAM aliases WRAM and IO reads change on
+/// their own, so code there is always fetched through the bus.
+fn cacheable(address: u16) -> bool {
+    matches!(address, 0x0000..=0x7FFF | 0xA000..=0xDFFF | 0xFF80..=0xFFFE)
+}
+
+/// Returns whether INSTRUCTION can continue anywhere but the next address
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jp(..)
+            | Instruction::JpHl
+            | Instruction::Jr(..)
+            | Instruction::Call(..)
+            | Instruction::Ret(_)
+            | Instruction::Reti
+            | Instruction::Rst(_)
+            | Instruction::Halt
+            | Instruction::Stop
+            | Instruction::Illegal(_)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cartridge::test::rom;
+    use cartridge::{Cartridge, RomOnly};
+    use cpu::{Condition, Operand, CPU, R8};
+    use mbc1::Mbc1;
+    use mbc3::Mbc3;
+    use mbc5::Mbc5;
+    use mmu::Mmu;
+    use model::Model;
+    use rtc::{RTC_DL, RTC_S};
+
+    /// Two 16 KiB banks at 0x4000, switched by writing the bank number anywhere below 0x8000
+    #[derive(Clone)]
+    struct BankedBus {
+        mem: [u8; 0x10000],
+        banks: [[u8; 0x4000]; 2],
+        bank: usize,
+    }
+
+    impl Bus for BankedBus {
+        fn read(&mut self, address: u16) -> u8 {
+            match address {
+                0x4000..=0x7FFF => self.banks[self.bank][address as usize - 0x4000],
+                _ => self.mem[address as usize],
+            }
+        }
+        fn write(&mut self, address: u16, value: u8) {
+            match address {
+                0x0000..=0x7FFF => self.bank = value as usize & 1,
+                _ => self.mem[address as usize] = value,
+            }
+        }
+        fn bank(&self, address: u16) -> u16 {
+            match address {
+                0x4000..=0x7FFF => self.bank as u16,
+                _ => 0,
+            }
+        }
+    }
+
+    /// Runs the same program with and without the block cache, checking both agree on
+    /// every register and every cycle after each step
+    /// Each CPU gets a bus of its own from BUS
+    /// Returns the uncached and cached CPUs afterwards
+    fn run_both<B: Bus, F: Fn() -> B>(bus: F, pc: u16, steps: usize) -> (CPU<B>, CPU<B>) {
+        let mut cpu = CPU::post_boot(bus(), Model::Dmg, 0x0B);
+        let mut registers = cpu.registers();
+        registers.pc = pc;
+        cpu.set_registers(&registers);
+        let mut other = CPU::post_boot(bus(), Model::Dmg, 0x0B);
+        other.set_registers(&registers);
+        other.set_block_cache(true);
+        for _ in 0..steps {
+            assert_eq!(cpu.step(), other.step());
+            assert_eq!(cpu.registers(), other.registers());
+            assert_eq!(cpu.cycles(), other.cycles());
+        }
+        (cpu, other)
+    }
+
+    // Checks that Tetris runs exactly the same with the block cache on
+    #[test]
+    fn cache_matches_plain_execution() {
+        let rom = include_bytes!("../tetris.gb");
+        let mut mem = [0u8; 0x10000];
+        mem[..rom.len()].copy_from_slice(rom);
+        for &(address, value) in Model::Dmg.io().iter() {
+            mem[address as usize] = value;
+        }
+        let (cpu, other) = run_both(|| mem, 0x0100, 100_000);
+        assert!(cpu.bus()[..] == other.bus()[..]);
+    }
+    // Checks that code rewriting itself in WRAM is decoded again after each write
+    #[test]
+    fn cache_drops_overwritten_code() {
+        let mut mem = [0u8; 0x10000];
+        // 0xC000: INC B ; LD A,$04 ; LD ($C001),A ; JR $C000
+        // The first pass turns INC B into INC B, then the next into INC B ; INC B and so on
+        let code = [0x04, 0x3E, 0x04, 0xEA, 0x01, 0xC0, 0x18, 0xF8];
+        mem[0xC000..0xC000 + code.len()].copy_from_slice(&code);
+        let (cpu, other) = run_both(|| mem, 0xC000, 100);
+        assert!(cpu.bus()[..] == other.bus()[..]);
+    }
+    // Checks that code in WRAM is decoded again after a write through echo RAM
+    #[test]
+    fn cache_drops_code_overwritten_through_echo_ram() {
+        let mmu = || {
+            let mut mmu = Mmu::post_boot(Model::Dmg, Box::new(RomOnly::new(rom(2), 0)));
+            // 0xC000: INC B ; LD A,$0C ; LD ($E000),A ; JR $C000
+            // The first pass turns INC B into INC C through its mirror at 0xE000
+            let code = [0x04, 0x3E, 0x0C, 0xEA, 0x00, 0xE0, 0x18, 0xF8];
+            for (i, &byte) in code.iter().enumerate() {
+                mmu.write(0xC000 + i as u16, byte);
+            }
+            mmu
+        };
+        let (_, other) = run_both(mmu, 0xC000, 40);
+        assert_eq!((other.registers().b, other.registers().c), (1, 0x13 + 9));
+    }
+    // Checks that code in cartridge RAM is decoded again after a write where the RAM repeats
+    #[test]
+    fn cache_drops_code_overwritten_through_repeated_ram() {
+        let mmu = || {
+            // 2 KiB of RAM, which repeats every 0x800 across 0xA000-0xBFFF
+            let mut mmu = Mmu::post_boot(Model::Dmg, Box::new(Mbc1::new(rom(2), 0x800)));
+            mmu.write(0x0000, 0x0A);
+            // 0xA000: INC B ; LD A,$0C ; LD ($A800),A ; JR $A000
+            let code = [0x04, 0x3E, 0x0C, 0xEA, 0x00, 0xA8, 0x18, 0xF8];
+            for (i, &byte) in code.iter().enumerate() {
+                mmu.write(0xA000 + i as u16, byte);
+            }
+            mmu
+        };
+        let (_, other) = run_both(mmu, 0xA000, 40);
+        assert_eq!((other.registers().b, other.registers().c), (1, 0x13 + 9));
+    }
+    // Checks that the same address in two banks is cached separately
+    #[test]
+    fn cache_follows_bank_switches() {
+        let mut bus = BankedBus {
+            mem: [0u8; 0x10000],
+            banks: [[0u8; 0x4000]; 2],
+            bank: 0,
+        };
+        // 0x0000: CALL $4000 ; LD ($2000),A ; INC A ; JR $0000
+        let code = [0xCD, 0x00, 0x40, 0xEA, 0x00, 0x20, 0x3C, 0x18, 0xF7];
+        bus.mem[..code.len()].copy_from_slice(&code);
+        // Bank 0 increments B and bank 1 increments C before returning
+        bus.banks[0][..2].copy_from_slice(&[0x04, 0xC9]);
+        bus.banks[1][..2].copy_from_slice(&[0x0C, 0xC9]);
+        let (_, other) = run_both(|| bus.clone(), 0x0000, 200);
+        assert!(other.registers().b > 0 && other.registers().c > 0);
+    }
+    // Checks that an instruction running from 0x3FFF into 0x4000 follows the bank at 0x4000,
+    // even while bank 0 is mapped on both sides
+    #[test]
+    fn cache_splits_blocks_at_bank_windows() {
+        let mmu = || {
+            let mut rom = rom(2);
+            // 0x0150: XOR A ; LD ($2000),A ; CALL $3FFF ; LD B,A
+            //         INC A ; LD ($2000),A ; CALL $3FFF ; LD C,A ; JR $
+            let code = [
+                0xAF, 0xEA, 0x00, 0x20, 0xCD, 0xFF, 0x3F, 0x47, 0x3C, 0xEA, 0x00, 0x20, 0xCD,
+                0xFF, 0x3F, 0x4F, 0x18, 0xFE,
+            ];
+            rom[0x0150..0x0150 + code.len()].copy_from_slice(&code);
+            // 0x3FFF: LD A,n with n the bank number at 0x4000, then a NOP and RET in each bank
+            rom[0x3FFF] = 0x3E;
+            rom[0x0002] = 0xC9;
+            rom[0x4002] = 0xC9;
+            Mmu::post_boot(Model::Dmg, Box::new(Mbc5::new(rom, 0, false)))
+        };
+        let (_, other) = run_both(mmu, 0x0150, 40);
+        assert_eq!((other.registers().b, other.registers().c), (0, 1));
+    }
+    // Checks that code run from a clock register, which has no bank, is never cached
+    #[test]
+    fn cache_skips_memory_without_a_bank() {
+        let mmu = || {
+            let mut rom = rom(2);
+            // 0x0150: Enable RAM, map S and call it, then map DL and call it, and repeat
+            let code = [
+                0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0x08, 0xEA, 0x00, 0x40, 0xCD, 0x00, 0xA0,
+                0x3E, 0x0B, 0xEA, 0x00, 0x40, 0xCD, 0x00, 0xA0, 0x18, 0xE9,
+            ];
+            rom[0x0150..0x0150 + code.len()].copy_from_slice(&code);
+            let mut mbc = Mbc3::new(rom, 0, true);
+            // Every address reads the latched register: S is INC B, DL is RET
+            {
+                let rtc = mbc.rtc_mut().unwrap();
+                rtc.write(RTC_S, 0x04);
+                rtc.write(RTC_DL, 0xC9);
+                rtc.latch();
+            }
+            let mut mmu = Mmu::post_boot(Model::Dmg, Box::new(mbc));
+            // Running off the end of the clock registers lands on a RET
+            mmu.write(0xC000, 0xC9);
+            mmu
+        };
+        let (_, other) = run_both(mmu, 0x0150, 20_000);
+        assert!(other.registers().b > 0);
+    }
+    // Checks that a write drops the blocks decoded from that address and no others
+    #[test]
+    fn cache_drops_only_blocks_written_over() {
+        let mut cache = BlockCache::new();
+        let mut mem = [0u8; 0x10000];
+        // 0x0100: INC B ; RET, and the same at 0x0180, 0x01FF and 0xC000
+        for &address in [0x0100, 0x0180, 0x01FF, 0xC000].iter() {
+            mem[address..address + 2].copy_from_slice(&[0x04, 0xC9]);
+            assert!(cache.fetch(&mut mem, address as u16).is_some());
+        }
+        assert_eq!(cache.index.len(), 4);
+        // Nothing was decoded from 0x0102, nor anywhere near 0x8000
+        cache.write(0x0102);
+        cache.write(0x8000);
+        assert_eq!(cache.index.len(), 4);
+        cache.write(0x0101);
+        assert!(!cache.index.contains_key(&(0, 0x0100)));
+        assert_eq!(cache.index.len(), 3);
+        // A block crossing into the next page is found from either page
+        cache.write(0x0200);
+        assert!(!cache.index.contains_key(&(0, 0x01FF)));
+        assert!(cache.index.contains_key(&(0, 0x0180)));
+        assert_eq!((cache.pages[0x01].len(), cache.pages[0x02].len()), (1, 0));
+        // The freed slots are used again
+        assert!(cache.fetch(&mut mem, 0x0100).is_some());
+        assert_eq!(cache.blocks.len(), 4);
+        cache.clear();
+        assert!(cache.index.is_empty() && cache.pages.iter().all(|page| page.is_empty()));
+    }
+    // Checks that the oldest blocks make way once the cache is full
+    #[test]
+    fn cache_evicts_oldest_blocks() {
+        let mut cache = BlockCache::new();
+        // Every address holds a RET, which makes a block of its own
+        let mut mem = [0xC9u8; 0x10000];
+        let ret = Instruction::Ret(Condition::Always);
+        for address in 0..MAX_BLOCKS as u16 + 2 {
+            assert_eq!(cache.fetch(&mut mem, address), Some((ret, 1)));
+        }
+        assert_eq!((cache.index.len(), cache.blocks.len()), (MAX_BLOCKS, MAX_BLOCKS));
+        assert!(!cache.index.contains_key(&(0, 0x0000)));
+        assert!(!cache.index.contains_key(&(0, 0x0001)));
+        assert!(cache.index.contains_key(&(0, 0x0002)));
+        assert!(cache.index.contains_key(&(0, MAX_BLOCKS as u16 + 1)));
+        assert_eq!(cache.pages[0x00].len(), PAGE_SIZE - 2);
+    }
+    // Checks that code outside cacheable memory is never cached
+    #[test]
+    fn cache_skips_uncacheable_memory() {
+        let mut cache = BlockCache::new();
+        let mut mem = [0u8; 0x10000];
+        for &address in [0x8000, 0x9FFF, 0xE000, 0xFE00, 0xFF00, 0xFFFF].iter() {
+            assert_eq!(cache.fetch(&mut mem, address), None);
+        }
+        assert_eq!(cache.fetch(&mut mem, 0xFF80), Some((Instruction::Nop, 1)));
+        // A block ends at the edge of cacheable memory
+        mem[0x7FFE] = 0x04;
+        assert_eq!(
+            cache.fetch(&mut mem, 0x7FFE),
+            Some((Instruction::Inc(Operand::Reg(R8::B)), 1))
+        );
+        assert_eq!(cache.fetch(&mut mem, 0x7FFF), Some((Instruction::Nop, 1)));
+        assert_eq!(cache.fetch(&mut mem, 0x8000), None);
+    }
+    // Times Tetris through the memory map with and without the block cache, checking the cache
+    // pays for itself. Decoding is only part of each step, the rest being execution and bus
+    // ticks both share, so expect around 1.5x rather than several times.
+    // Run with cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn cache_benchmark() {
+        use cartridge;
+        use std::time::Instant;
+        const STEPS: usize = 20_000_000;
+        let mut times = Vec::new();
+        for &cached in [false, true].iter() {
+            let cart = cartridge::load(include_bytes!("../tetris.gb").to_vec()).unwrap();
+            let mut cpu = CPU::post_boot(Mmu::post_boot(Model::Dmg, cart), Model::Dmg, 0x0B);
+            cpu.set_block_cache(cached);
+            let start = Instant::now();
+            for _ in 0..STEPS {
+                cpu.step().unwrap();
+            }
+            times.push(start.elapsed());
+        }
+        let speedup = times[0].as_secs_f64() / times[1].as_secs_f64();
+        println!("plain {:?}, cached {:?}, {:.2}x", times[0], times[1], speedup);
+        assert!(speedup > 1.2, "the block cache only ran {:.2}x as fast", speedup);
+    }
+}
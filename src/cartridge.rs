@@ -0,0 +1,228 @@
+use std::fmt;
+
+use header::{Header, HeaderError, Mapper};
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+use rtc::Rtc;
+
+/// Bank reported for cartridge RAM that is disabled or missing, which no real bank can be
+pub const NO_BANK: u16 = 0xFFFE;
+
+/// A cartridge, as seen through the two address ranges it answers to
+/// ROM and any mapper registers sit at 0x0000-0x7FFF, external RAM at 0xA000-0xBFFF
+pub trait Cartridge {
+    /// USAGE: cart.read(ADDR) where ADDR is in 0x0000-0x7FFF or 0xA000-0xBFFF
+    /// Returns the byte currently mapped at ADDR, or 0xFF where nothing drives the bus
+    fn read(&mut self, address: u16) -> u8;
+    /// USAGE: cart.write(ADDR, N) where ADDR is in 0x0000-0x7FFF or 0xA000-0xBFFF
+    /// Writes to ROM go to the mapper, if there is one
+    fn write(&mut self, address: u16, value: u8);
+    /// USAGE: cart.bank(ADDR)
+    /// Returns which ROM or RAM bank is mapped at ADDR, see bus.bank()
+    fn bank(&self, _address: u16) -> u16 {
+        0
+    }
+    /// USAGE: cart.mirror(ADDR)
+    /// Returns the first address that reaches the same memory as ADDR, see bus.mirror()
+    fn mirror(&self, address: u16) -> u16 {
+        address
+    }
+    /// USAGE: cart.tick()
+    /// Advances anything on the cartridge that keeps time by one m-cycle
+    fn tick(&mut self) {}
+    /// USAGE: cart.ram()
+    /// Returns the external RAM as a save file holds it, which is empty without any RAM
+    /// Only worth keeping when the header says the cartridge has a battery
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+    /// USAGE: cart.load_ram(SAVE) where SAVE came from cart.ram(), e.g. in an earlier run
+    /// Copies SAVE into the external RAM, as much of it as fits
+    fn load_ram(&mut self, _save: &[u8]) {}
+    /// USAGE: cart.rtc()
+    /// Returns the real-time clock on the cartridge, if it has one, e.g. to save it
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+    /// USAGE: cart.rtc_mut()
+    /// Returns the real-time clock on the cartridge, if it has one, e.g. to load a save
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+    /// USAGE: cart.rumble_events()
+    /// Returns every time the rumble motor started or stopped since the last call, oldest first
+    fn rumble_events(&mut self) -> Vec<RumbleEvent> {
+        Vec::new()
+    }
+}
+
+/// The rumble motor of a cartridge starting or stopping
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RumbleEvent {
+    /// M-cycles the cartridge had been ticked for when it happened
+    pub cycle: u64,
+    /// Whether the motor is now running
+    pub on: bool,
+}
+
+/// USAGE: cartridge::copy_save(RAM, SAVE)
+/// Copies SAVE over the start of RAM, as much of it as fits, for cart.load_ram()
+pub fn copy_save(ram: &mut [u8], save: &[u8]) {
+    let size = ram.len().min(save.len());
+    ram[..size].copy_from_slice(&save[..size]);
+}
+
+/// USAGE: cartridge::ram_mirror(ADDR, SIZE) where SIZE is the size of the RAM
+/// Returns the first address reaching the same byte of RAM as ADDR, for mappers whose RAM
+/// repeats across 0xA000-0xBFFF when it is smaller than 8 KiB
+pub fn ram_mirror(address: u16, size: usize) -> u16 {
+    match address {
+        0xA000..=0xBFFF if size > 0 && size < 0x2000 => {
+            0xA000 + ((address as usize - 0xA000) % size) as u16
+        }
+        _ => address,
+    }
+}
+
+/// A cartridge with no mapper: 32 KiB of ROM and optionally up to 8 KiB of RAM
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    /// USAGE: RomOnly::new(ROM, RAM_SIZE) where RAM_SIZE is 0 for no RAM at all
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size],
+        }
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn read(&mut self, address: u16) -> u8 {
+        let (memory, offset) = match address {
+            0x0000..=0x7FFF => (&self.rom, address as usize),
+            _ => (&self.ram, address as usize - 0xA000),
+        };
+        memory.get(offset).cloned().unwrap_or(0xFF)
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        if let 0xA000..=0xBFFF = address {
+            if let Some(byte) = self.ram.get_mut(address as usize - 0xA000) {
+                *byte = value;
+            }
+        }
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn load_ram(&mut self, save: &[u8]) {
+        copy_save(&mut self.ram, save);
+    }
+}
+
+/// Why a ROM image cannot be run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    Header(HeaderError),
+    /// The header is fine, but its mapper is not emulated
+    Unsupported(Mapper),
+}
+
+impl From<HeaderError> for LoadError {
+    fn from(error: HeaderError) -> Self {
+        LoadError::Header(error)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Header(ref error) => error.fmt(f),
+            LoadError::Unsupported(mapper) => write!(f, "Unsupported mapper {:?}", mapper),
+        }
+    }
+}
+
+/// USAGE: cartridge::load(ROM) where ROM is a whole ROM image
+/// Returns the cartridge ROM describes, with its header checked and the right mapper in it
+pub fn load(rom: Vec<u8>) -> Result<Box<dyn Cartridge>, LoadError> {
+    let header = Header::parse(&rom)?;
+    match header.cartridge_type.mapper {
+        Mapper::RomOnly => Ok(Box::new(RomOnly::new(rom, header.ram_size))),
+        Mapper::Mbc1 => Ok(Box::new(Mbc1::new(rom, header.ram_size))),
+        Mapper::Mbc2 => Ok(Box::new(Mbc2::new(rom))),
+        Mapper::Mbc3 => {
+            let timer = header.cartridge_type.timer;
+            Ok(Box::new(Mbc3::new(rom, header.ram_size, timer)))
+        }
+        Mapper::Mbc5 => {
+            let rumble = header.cartridge_type.rumble;
+            Ok(Box::new(Mbc5::new(rom, header.ram_size, rumble)))
+        }
+        mapper => Err(LoadError::Unsupported(mapper)),
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// Returns a ROM of BANKS banks, each starting with its bank number as a 16-bit word
+    /// Shared by the tests of every mapper.
+    pub fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * 0x4000];
+        for bank in 0..banks {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    // Checks that a ROM is loaded behind the mapper its header names
+    #[test]
+    fn can_load_cartridges() {
+        let rom = include_bytes!("../tetris.gb").to_vec();
+        let mut cartridge = load(rom.clone()).unwrap();
+        assert_eq!((cartridge.read(0x0100), cartridge.read(0x0101)), (0x00, 0xC3));
+        // Tetris has no RAM
+        cartridge.write(0xA000, 0x42);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+        let error = load(rom[..0x4000].to_vec()).err().unwrap();
+        assert_eq!(error.to_string(), "Header says the ROM is 32768 bytes, but it is 16384 bytes");
+        // RAM comes back from a save, cut or padded to the size the header gives
+        let mut cartridge = RomOnly::new(rom.clone(), 0x2000);
+        cartridge.load_ram(&[0x12; 0x4000]);
+        assert_eq!((cartridge.ram().len(), cartridge.read(0xBFFF)), (0x2000, 0x12));
+        cartridge.load_ram(&[0x34]);
+        assert_eq!((cartridge.read(0xA000), cartridge.read(0xA001)), (0x34, 0x12));
+        // An MBC2 has RAM whatever the header says
+        let mut mbc2 = rom.clone();
+        mbc2[0x0147] = 0x06;
+        mbc2[0x014D] = Header::checksum_of(&mbc2);
+        assert_eq!(load(mbc2).unwrap().ram().len(), 0x200);
+        // Only some MBC3 cartridges have a clock
+        let mut mbc3 = rom.clone();
+        mbc3[0x0147] = 0x0F;
+        mbc3[0x014D] = Header::checksum_of(&mbc3);
+        assert!(load(mbc3.clone()).unwrap().rtc().is_some());
+        mbc3[0x0147] = 0x11;
+        mbc3[0x014D] = Header::checksum_of(&mbc3);
+        assert!(load(mbc3).unwrap().rtc().is_none());
+        // Rumble cartridges report the motor
+        let mut mbc5 = rom.clone();
+        mbc5[0x0147] = 0x1C;
+        mbc5[0x014D] = Header::checksum_of(&mbc5);
+        let mut cartridge = load(mbc5).unwrap();
+        cartridge.write(0x4000, 0x08);
+        assert_eq!(cartridge.rumble_events(), [RumbleEvent { cycle: 0, on: true }]);
+        let mut mbc6 = rom;
+        mbc6[0x0147] = 0x20;
+        mbc6[0x014D] = Header::checksum_of(&mbc6);
+        assert_eq!(load(mbc6).err(), Some(LoadError::Unsupported(Mapper::Mbc6)));
+    }
+}
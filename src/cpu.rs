@@ -1,58 +1,291 @@
-struct CPU {
+use std::fmt;
+
+use bus::Bus;
+use cache::BlockCache;
+use instruction::{Alu, Instruction, Shift};
+use interrupt::{self, Interrupt};
+use model::Model;
+use opcode::OPCODES;
+
+/// The SM83 core, attached to whatever memory B maps into its address space
+/// The full machine plugs in its memory map, while tools and tests can use a flat 64 KiB array
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU<B: Bus> {
+    bus: B,
     clock: Clock,
     reg8: [u8; 7],
-    m: u8,
-    t: u8,
     pc: u16,
     sp: u16,
     flags: Flags,
+    ime: bool,
+    // Set by EI, which only enables interrupts after the instruction following it
+    ime_scheduled: bool,
+    mode: Mode,
+    // Set by the HALT bug, which keeps PC from advancing past the next opcode fetched
+    halt_bug: bool,
+    // Decoded blocks of code, when the block cache is turned on
+    cache: Option<BlockCache>,
+    illegal_policy: IllegalPolicy,
+    // The illegal opcode the CPU locked up on this step, until step() returns it
+    illegal_opcode: Option<IllegalOpcode>,
+}
+
+/// Whether the CPU is executing instructions or sitting in one of its low power modes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Running,
+    /// Entered by HALT, left as soon as any enabled interrupt is requested
+    Halted,
+    /// Entered by STOP, left when a joypad input line goes low
+    Stopped,
+    /// Entered by an illegal opcode, never left until the registers are reset
+    Locked,
+}
+
+/// What the CPU does when it fetches one of the 11 illegal opcodes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IllegalPolicy {
+    /// Lock up like the hardware does, ignoring interrupts until reset
+    Lock,
+    /// Lock up, and have cpu.step() return the opcode and where it came from
+    Report,
+}
+
+/// An illegal opcode the CPU ran into, with the bank and address it was fetched from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IllegalOpcode {
+    pub opcode: u8,
+    pub bank: u16,
+    pub pc: u16,
+}
+
+/// Prints e.g. `Illegal opcode $DD at 01:4A2C`, bank first as in debugger addresses
+impl fmt::Display for IllegalOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Illegal opcode ${:02X} at {:02X}:{:04X}", self.opcode, self.bank, self.pc)
+    }
+}
+
+/// A snapshot of everything a program can see of the CPU
+/// Read it with cpu.registers() and write it back with cpu.set_registers(REGS)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    /// The flags, ZNHC from bit 7 down to bit 4. The low nibble always reads as zero.
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+    pub mode: Mode,
+}
+
+impl Registers {
+    /// USAGE: regs.af()
+    /// Returns A and F as one 16-bit register
+    pub fn af(&self) -> u16 {
+        u8s_to_u16(self.a, self.f)
+    }
+    /// USAGE: regs.bc()
+    /// Returns B and C as one 16-bit register
+    pub fn bc(&self) -> u16 {
+        u8s_to_u16(self.b, self.c)
+    }
+    /// USAGE: regs.de()
+    /// Returns D and E as one 16-bit register
+    pub fn de(&self) -> u16 {
+        u8s_to_u16(self.d, self.e)
+    }
+    /// USAGE: regs.hl()
+    /// Returns H and L as one 16-bit register
+    pub fn hl(&self) -> u16 {
+        u8s_to_u16(self.h, self.l)
+    }
+    /// USAGE: regs.set_af(NN)
+    /// Sets A and F from NN, dropping the low nibble of F
+    pub fn set_af(&mut self, value: u16) {
+        let (a, f) = u16_to_u8s(value);
+        self.a = a;
+        self.f = f & 0xF0;
+    }
+    /// USAGE: regs.set_bc(NN)
+    pub fn set_bc(&mut self, value: u16) {
+        let (b, c) = u16_to_u8s(value);
+        self.b = b;
+        self.c = c;
+    }
+    /// USAGE: regs.set_de(NN)
+    pub fn set_de(&mut self, value: u16) {
+        let (d, e) = u16_to_u8s(value);
+        self.d = d;
+        self.e = e;
+    }
+    /// USAGE: regs.set_hl(NN)
+    pub fn set_hl(&mut self, value: u16) {
+        let (h, l) = u16_to_u8s(value);
+        self.h = h;
+        self.l = l;
+    }
 }
 
-impl CPU {
-    pub fn new() -> Self {
+impl fmt::Display for Registers {
+    /// Writes the registers the way common trace logs do, e.g.
+    /// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X}",
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc
+        )
+    }
+}
+
+impl<B: Bus + Default> Default for CPU<B> {
+    fn default() -> Self {
+        CPU::new(B::default())
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    /// USAGE: CPU::new(BUS) where BUS is the memory the CPU is attached to
+    /// Returns a CPU with every register cleared, which owns BUS from then on
+    pub fn new(bus: B) -> Self {
         CPU {
-            clock: Clock { m: 0, t: 0 },
+            bus,
+            clock: Clock { t: 0 },
             reg8: [0; 7],
-            m: 0,
-            t: 0,
             pc: 0,
             sp: 0,
             flags: Flags {
-                add: false,
+                zero: false,
+                subtract: false,
                 half_carry: false,
                 carry: false,
             },
+            ime: false,
+            ime_scheduled: false,
+            mode: Mode::Running,
+            halt_bug: false,
+            cache: None,
+            illegal_policy: IllegalPolicy::Lock,
+            illegal_opcode: None,
+        }
+    }
+    /// USAGE: CPU::post_boot(BUS, MODEL, CHECKSUM) where CHECKSUM is the header checksum at 0x014D
+    /// Returns a CPU in the state MODEL's boot ROM leaves it in, ready to run a cartridge
+    /// from 0x0100 without a boot ROM. See model.io() for the matching IO registers.
+    pub fn post_boot(bus: B, model: Model, header_checksum: u8) -> Self {
+        let mut cpu = CPU::new(bus);
+        cpu.set_registers(&model.registers(header_checksum));
+        cpu
+    }
+    /// USAGE: self.bus()
+    /// Returns the memory the CPU is attached to
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+    /// USAGE: self.bus_mut()
+    /// Returns the memory the CPU is attached to, to change it between steps
+    /// Code changed this way is not seen by the block cache, see self.flush_block_cache()
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+    /// USAGE: self.into_bus()
+    /// Consumes the CPU, giving back the memory it was attached to
+    pub fn into_bus(self) -> B {
+        self.bus
+    }
+    /// USAGE: self.internal_cycle()
+    /// Spends one m-cycle without touching memory, ticking everything on the bus
+    /// The clock advances 4 t-cycles for every m-cycle
+    fn internal_cycle(&mut self) {
+        self.bus.tick();
+        self.clock.t += 4;
+    }
+    /// USAGE: self.read_cycle(ADDR)
+    /// Spends one m-cycle reading ADDR, so the read sees everything that happened that cycle
+    fn read_cycle(&mut self, address: u16) -> u8 {
+        self.internal_cycle();
+        self.bus.read(address)
+    }
+    /// USAGE: self.write_cycle(ADDR, N)
+    /// Spends one m-cycle writing N to ADDR
+    fn write_cycle(&mut self, address: u16, value: u8) {
+        self.internal_cycle();
+        self.bus.write(address, value);
+        if let Some(ref mut cache) = self.cache {
+            cache.write(self.bus.mirror(address));
+        }
+    }
+    /// USAGE: self.set_block_cache(ENABLED)
+    /// Turns caching of decoded blocks of code on or off, dropping anything already cached
+    /// Cached and uncached execution take exactly the same cycles and tick the bus the same
+    /// way, the cache only saves decoding. Blocks are keyed by bus.bank() and dropped when
+    /// the CPU writes over them; after changing code or banks in memory any other way, call
+    /// self.flush_block_cache().
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.cache = if enabled { Some(BlockCache::new()) } else { None };
+    }
+    /// USAGE: self.set_illegal_policy(POLICY)
+    /// Chooses whether illegal opcodes just lock the CPU up or are also reported
+    pub fn set_illegal_policy(&mut self, policy: IllegalPolicy) {
+        self.illegal_policy = policy;
+    }
+    /// USAGE: self.flush_block_cache()
+    /// Drops every cached block, e.g. after memory was changed behind the CPU's back
+    pub fn flush_block_cache(&mut self) {
+        if let Some(ref mut cache) = self.cache {
+            cache.clear();
         }
     }
-    /// USAGE: self.tick(time) where time is the number of m-cycles
-    /// Used to set M and T registers to time taken for previous instruction
-    /// t register advances 4 cycles for every 1 m-cycle
-    pub fn tick(&mut self, time: u8) {
-        self.m = time;
-        self.t = time * 4;
+    /// USAGE: self.cycles()
+    /// Returns the number of t-cycles elapsed since power on
+    /// This is the timestamp every other part of the system is timed against
+    pub fn cycles(&self) -> u64 {
+        self.clock.t
     }
-    /// USAGE: self.update_clock()
-    /// Used to update clock to represent current time
-    /// zeroes out m and t registers after adding them to clock.m and clock.t
-    pub fn update_clock(&mut self) {
-        self.clock.m += self.m;
-        self.clock.t += self.t;
-        self.m = 0;
-        self.t = 0;
+    /// USAGE: self.frame_cycles()
+    /// Returns the number of t-cycles elapsed since the start of the current frame
+    pub fn frame_cycles(&self) -> u64 {
+        self.clock.t % CYCLES_PER_FRAME
     }
-    /// USAGE: self.and(R) where R is the register to be compared to A
+    /// USAGE: self.and(R) where R is the operand to be compared to A
     /// implements AND r instruction
     /// Returns logical AND of A and R and stores the result in A
-    pub fn and(&mut self, register: R8) {
-        let res = self.fetch8(register) | self.fetch8(R8::A);
-        &mut self.set8(R8::A, res);
+    pub fn and<O: Into<Operand>>(&mut self, operand: O) {
+        let res = self.read8(operand) & self.fetch8(R8::A);
+        self.logic(res, true);
     }
-    /// USAGE: self.or(R) where R is the register to be compared to A
+    /// USAGE: self.or(R) where R is the operand to be compared to A
     /// Implements OR r instruction
     /// Returns logical OR of A and R and stores the result in A
-    pub fn or(&mut self, register: R8) {
-        let res = self.fetch8(register) | self.fetch8(R8::A);
+    pub fn or<O: Into<Operand>>(&mut self, operand: O) {
+        let res = self.read8(operand) | self.fetch8(R8::A);
+        self.logic(res, false);
+    }
+    /// USAGE: self.xor(R) where R is the operand to be compared to A
+    /// Implements XOR r instruction
+    /// Returns logical XOR of A and R and stores the result in A
+    pub fn xor<O: Into<Operand>>(&mut self, operand: O) {
+        let res = self.read8(operand) ^ self.fetch8(R8::A);
+        self.logic(res, false);
+    }
+    /// Stores the result of a logical operation in A
+    /// Sets Z from the result, clears N and C, and sets H only for AND
+    fn logic(&mut self, res: u8, half_carry: bool) {
         self.set8(R8::A, res);
+        self.flags = Flags {
+            zero: res == 0,
+            subtract: false,
+            half_carry,
+            carry: false,
+        };
     }
     /// USAGE: self.fetch8(R) where R is the register to fetch
     /// Used internally to access the 8-bit register array
@@ -76,6 +309,7 @@ impl CPU {
         match register {
             R16::PC => self.pc,
             R16::SP => self.sp,
+            R16::AF => u8s_to_u16(self.fetch8(R8::A), self.flags.to_byte()),
             R16::BC => u8s_to_u16(self.fetch8(R8::B), self.fetch8(R8::C)),
             R16::DE => u8s_to_u16(self.fetch8(R8::D), self.fetch8(R8::E)),
             R16::HL => u8s_to_u16(self.fetch8(R8::H), self.fetch8(R8::L)),
@@ -98,7 +332,6 @@ impl CPU {
             };
             *reg = value;
         }
-        self.tick(2);
     }
     /// USAGE: self.set16(R, N) where R is the 16-bit register to set and N is a 16-bit constant
     /// Used internally to set 16-bit registers or 2 8-bit registers
@@ -107,6 +340,10 @@ impl CPU {
         match register {
             R16::PC => self.pc = value,
             R16::SP => self.sp = value,
+            R16::AF => {
+                self.set8(R8::A, split.0);
+                self.flags = Flags::from_byte(split.1);
+            }
             R16::BC => {
                 self.set8(R8::B, split.0);
                 self.set8(R8::C, split.1);
@@ -121,46 +358,723 @@ impl CPU {
             }
             R16::CONST(_) => panic!("Tried to set 16-bit constant!"),
         }
-        self.tick(3);
+    }
+    /// USAGE: self.read8(OP) where OP is the 8-bit operand to read
+    /// Registers and constants are read directly, memory operands are read through the bus
+    /// in one m-cycle. (HL+) and (HL-) adjust HL after the access
+    pub fn read8<O: Into<Operand>>(&mut self, operand: O) -> u8 {
+        match operand.into() {
+            Operand::Reg(register) => self.fetch8(register),
+            operand => {
+                let address = self.address(operand);
+                self.read_cycle(address)
+            }
+        }
+    }
+    /// USAGE: self.write8(OP, N) where OP is the 8-bit operand to write and N the value
+    /// Registers are set directly, memory operands are written through the bus
+    /// in one m-cycle. (HL+) and (HL-) adjust HL after the access
+    pub fn write8<O: Into<Operand>>(&mut self, operand: O, value: u8) {
+        match operand.into() {
+            Operand::Reg(register) => self.set8(register, value),
+            operand => {
+                let address = self.address(operand);
+                self.write_cycle(address, value);
+            }
+        }
+    }
+    /// USAGE: self.write16(OP, NN) where OP is a memory operand and NN the value
+    /// Writes NN little-endian to the address of OP and the one after it, as in LD (nn),SP
+    /// Each byte takes its own m-cycle
+    pub fn write16(&mut self, operand: Operand, value: u16) {
+        let address = self.address(operand);
+        let (high, low) = u16_to_u8s(value);
+        self.write_cycle(address, low);
+        self.write_cycle(address.wrapping_add(1), high);
+    }
+    /// Resolves the address of a memory operand, applying the (HL+) and (HL-) side effects
+    fn address(&mut self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Reg(_) => panic!("Tried to take the address of a register!"),
+            Operand::Ind(register) => self.fetch16(register),
+            Operand::HlInc => {
+                let hl = self.fetch16(R16::HL);
+                self.set16(R16::HL, hl.wrapping_add(1));
+                hl
+            }
+            Operand::HlDec => {
+                let hl = self.fetch16(R16::HL);
+                self.set16(R16::HL, hl.wrapping_sub(1));
+                hl
+            }
+            Operand::High(n) => 0xFF00 | n as u16,
+            Operand::HighC => 0xFF00 | self.fetch8(R8::C) as u16,
+            Operand::Abs(nn) => nn,
+        }
     }
     /// USAGE: self.load(TO, FROM) where TO is the destination and FROM is the source
     /// Implements LD n,m instruction
-    /// Only valid to load 8-bit registers to 8-bit registers
-    pub fn load(&mut self, to: R8, from: R8) {
+    /// Either side may be a register, and FROM may also be a constant or memory operand
+    pub fn load<T, F>(&mut self, to: T, from: F)
+    where
+        T: Into<Operand>,
+        F: Into<Operand>,
+    {
+        let (to, from) = (to.into(), from.into());
         if to != from {
-            let val = self.fetch8(from);
-            self.set8(to, val);
+            let val = self.read8(from);
+            self.write8(to, val);
         }
-        self.tick(1);
     }
-    /// USAGE: self.add8(A, B) where A and B are 8-bit registers
+    /// USAGE: self.add8(A, B) where A is an 8-bit register and B any 8-bit operand
     /// Implements 8-bit version of ADD n, m
-    pub fn add8(&mut self, fst: R8, snd: R8) {
-        use std::u8::MAX;
-        self.flags.add = true;
-        let (i, j) = (self.fetch8(fst), self.fetch8(snd));
-        let res = (i as u16) + (j as u16);
-        if res > (MAX as u16) {
-            self.flags.carry = true;
-            self.set8(fst, i.wrapping_add(j));
+    pub fn add8<O: Into<Operand>>(&mut self, fst: R8, snd: O) {
+        let (i, j) = (self.fetch8(fst), self.read8(snd));
+        let res = self.add_with_carry(i, j, false);
+        self.set8(fst, res);
+    }
+    /// USAGE: self.adc(R) where R is the operand to add to A
+    /// Implements ADC A,r instruction, adding R and the carry flag to A
+    pub fn adc<O: Into<Operand>>(&mut self, operand: O) {
+        let (i, j) = (self.fetch8(R8::A), self.read8(operand));
+        let carry = self.flags.carry;
+        let res = self.add_with_carry(i, j, carry);
+        self.set8(R8::A, res);
+    }
+    /// USAGE: self.sub(R) where R is the operand to subtract from A
+    /// Implements SUB r instruction
+    pub fn sub<O: Into<Operand>>(&mut self, operand: O) {
+        let (i, j) = (self.fetch8(R8::A), self.read8(operand));
+        let res = self.sub_with_carry(i, j, false);
+        self.set8(R8::A, res);
+    }
+    /// USAGE: self.sbc(R) where R is the operand to subtract from A
+    /// Implements SBC A,r instruction, subtracting R and the carry flag from A
+    pub fn sbc<O: Into<Operand>>(&mut self, operand: O) {
+        let (i, j) = (self.fetch8(R8::A), self.read8(operand));
+        let carry = self.flags.carry;
+        let res = self.sub_with_carry(i, j, carry);
+        self.set8(R8::A, res);
+    }
+    /// USAGE: self.cp(R) where R is the operand to compare A with
+    /// Implements CP r instruction, setting flags as SUB r would without changing A
+    pub fn cp<O: Into<Operand>>(&mut self, operand: O) {
+        let (i, j) = (self.fetch8(R8::A), self.read8(operand));
+        self.sub_with_carry(i, j, false);
+    }
+    /// USAGE: self.inc(R) where R is the operand to increment
+    /// Implements INC r instruction, which leaves the carry flag alone
+    pub fn inc<O: Into<Operand>>(&mut self, operand: O) {
+        let operand = operand.into();
+        let value = self.read8(operand);
+        let res = value.wrapping_add(1);
+        self.flags.zero = res == 0;
+        self.flags.subtract = false;
+        self.flags.half_carry = detect_half_carry(value, 1, false);
+        self.write8(operand, res);
+    }
+    /// USAGE: self.dec(R) where R is the operand to decrement
+    /// Implements DEC r instruction, which leaves the carry flag alone
+    pub fn dec<O: Into<Operand>>(&mut self, operand: O) {
+        let operand = operand.into();
+        let value = self.read8(operand);
+        let res = value.wrapping_sub(1);
+        self.flags.zero = res == 0;
+        self.flags.subtract = true;
+        self.flags.half_carry = detect_half_borrow(value, 1, false);
+        self.write8(operand, res);
+    }
+    /// USAGE: self.cpl()
+    /// Implements CPL instruction, inverting every bit of A
+    pub fn cpl(&mut self) {
+        let a = self.fetch8(R8::A);
+        self.set8(R8::A, !a);
+        self.flags.subtract = true;
+        self.flags.half_carry = true;
+    }
+    /// USAGE: self.daa()
+    /// Implements DAA instruction, adjusting A to binary-coded decimal after an addition or
+    /// subtraction of two BCD numbers. N, H and C tell it which operation happened and
+    /// which digits overflowed.
+    pub fn daa(&mut self) {
+        let mut a = self.fetch8(R8::A);
+        let mut carry = self.flags.carry;
+        if !self.flags.subtract {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.flags.half_carry || a & 0x0F > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.flags.half_carry {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+        self.set8(R8::A, a);
+        self.flags.zero = a == 0;
+        self.flags.half_carry = false;
+        self.flags.carry = carry;
+    }
+    /// USAGE: self.add_hl(R) where R is the 16-bit register to add to HL
+    /// Implements ADD HL,rr instruction, which spends one internal m-cycle on the addition
+    /// H and C come from carries out of bits 11 and 15, Z is left alone
+    pub fn add_hl(&mut self, register: R16) {
+        let (hl, value) = (self.fetch16(R16::HL), self.fetch16(register));
+        self.flags.subtract = false;
+        self.flags.half_carry = (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF;
+        self.flags.carry = (hl as u32) + (value as u32) > 0xFFFF;
+        self.set16(R16::HL, hl.wrapping_add(value));
+        self.internal_cycle();
+    }
+    /// USAGE: self.add_sp(E) where E is a signed 8-bit offset
+    /// Implements ADD SP,e instruction, which spends two internal m-cycles on the addition
+    /// Z and N are cleared, H and C come from adding E to the low byte of SP as unsigned
+    pub fn add_sp(&mut self, offset: i8) {
+        self.sp = self.offset_sp(offset);
+        self.internal_cycle();
+        self.internal_cycle();
+    }
+    /// USAGE: self.load_hl_sp(E) where E is a signed 8-bit offset
+    /// Implements LD HL,SP+e instruction, with the same flags as ADD SP,e
+    /// and one internal m-cycle less
+    pub fn load_hl_sp(&mut self, offset: i8) {
+        let value = self.offset_sp(offset);
+        self.set16(R16::HL, value);
+        self.internal_cycle();
+    }
+    /// USAGE: self.inc16(R) where R is the 16-bit register to increment
+    /// Implements INC rr instruction, which sets no flags and spends one internal m-cycle
+    pub fn inc16(&mut self, register: R16) {
+        let value = self.fetch16(register).wrapping_add(1);
+        self.set16(register, value);
+        self.internal_cycle();
+    }
+    /// USAGE: self.dec16(R) where R is the 16-bit register to decrement
+    /// Implements DEC rr instruction, which sets no flags and spends one internal m-cycle
+    pub fn dec16(&mut self, register: R16) {
+        let value = self.fetch16(register).wrapping_sub(1);
+        self.set16(register, value);
+        self.internal_cycle();
+    }
+    /// Returns I + J + CARRY, setting every flag as ADD and ADC do
+    fn add_with_carry(&mut self, i: u8, j: u8, carry: bool) -> u8 {
+        let res = (i as u16) + (j as u16) + (carry as u16);
+        self.flags = Flags {
+            zero: res as u8 == 0,
+            subtract: false,
+            half_carry: detect_half_carry(i, j, carry),
+            carry: res > u8::MAX as u16,
+        };
+        res as u8
+    }
+    /// Returns I - J - CARRY, setting every flag as SUB, SBC and CP do
+    fn sub_with_carry(&mut self, i: u8, j: u8, carry: bool) -> u8 {
+        let res = i.wrapping_sub(j).wrapping_sub(carry as u8);
+        self.flags = Flags {
+            zero: res == 0,
+            subtract: true,
+            half_carry: detect_half_borrow(i, j, carry),
+            carry: (i as u16) < (j as u16) + (carry as u16),
+        };
+        res
+    }
+    /// USAGE: self.step()
+    /// Services a pending interrupt if IME is set, otherwise fetches the opcode at PC,
+    /// executes it and leaves PC pointing at the next instruction
+    /// Returns the number of m-cycles the instruction or interrupt dispatch took, or under
+    /// IllegalPolicy::Report the illegal opcode the CPU just locked up on
+    /// The bus is ticked once for each of those m-cycles, at the moment it happens
+    pub fn step(&mut self) -> Result<u8, IllegalOpcode> {
+        let start = self.clock.t;
+        if self.mode != Mode::Running {
+            self.idle();
+        } else if self.ime && interrupt::pending(&mut self.bus) != 0 {
+            self.dispatch_interrupt();
         } else {
-            self.set8(fst, i + j);
+            // IME set by EI only becomes visible to the check above on the next step
+            if self.ime_scheduled {
+                self.ime = true;
+                self.ime_scheduled = false;
+            }
+            if !self.halt_bug && self.cached() {
+                return self.stepped(start);
+            }
+            let opcode = self.next8();
+            if self.halt_bug {
+                self.halt_bug = false;
+                self.pc = self.pc.wrapping_sub(1);
+            }
+            // Operand bytes always directly follow the opcode fetch, one m-cycle each
+            let mut bytes = [opcode, 0, 0];
+            let length = OPCODES[opcode as usize].length as usize;
+            for byte in bytes.iter_mut().take(length).skip(1) {
+                *byte = self.next8();
+            }
+            let instruction = Instruction::decode(&bytes).unwrap();
+            self.execute(instruction);
+        }
+        self.stepped(start)
+    }
+    /// Returns what self.step() does for a step begun at t-cycle START
+    fn stepped(&mut self, start: u64) -> Result<u8, IllegalOpcode> {
+        match self.illegal_opcode.take() {
+            Some(illegal) => Err(illegal),
+            None => Ok(((self.clock.t - start) / 4) as u8),
+        }
+    }
+    /// USAGE: self.mode()
+    /// Returns whether the CPU is running, halted or stopped
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+    /// USAGE: self.registers()
+    /// Returns a snapshot of every register, IME and the low power mode
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.fetch8(R8::A),
+            f: self.flags.to_byte(),
+            b: self.fetch8(R8::B),
+            c: self.fetch8(R8::C),
+            d: self.fetch8(R8::D),
+            e: self.fetch8(R8::E),
+            h: self.fetch8(R8::H),
+            l: self.fetch8(R8::L),
+            sp: self.sp,
+            pc: self.pc,
+            ime: self.ime,
+            mode: self.mode,
+        }
+    }
+    /// USAGE: self.set_registers(REGS)
+    /// Overwrites every register, IME and the low power mode with REGS
+    /// A pending EI or HALT bug is dropped, since neither is visible in REGS
+    pub fn set_registers(&mut self, registers: &Registers) {
+        self.reg8 = [
+            registers.a,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+        ];
+        self.flags = Flags::from_byte(registers.f);
+        self.sp = registers.sp;
+        self.pc = registers.pc;
+        self.ime = registers.ime;
+        self.ime_scheduled = false;
+        self.mode = registers.mode;
+        self.halt_bug = false;
+    }
+    /// Spends one m-cycle in HALT, STOP or a lockup, leaving it if the wakeup condition is met
+    /// Leaving a low power mode takes this whole cycle, execution resumes on the next step
+    /// Nothing wakes a locked up CPU
+    fn idle(&mut self) {
+        self.internal_cycle();
+        let wake = match self.mode {
+            Mode::Halted => interrupt::pending(&mut self.bus) != 0,
+            // Any of the four input lines of P1 going low ends STOP
+            Mode::Stopped => self.bus.read(P1) & 0x0F != 0x0F,
+            _ => false,
+        };
+        if wake {
+            self.mode = Mode::Running;
+        }
+    }
+    /// Implements HALT
+    /// With an interrupt already pending HALT does not halt at all. With IME clear this
+    /// triggers the HALT bug, reading the next byte twice. With IME set, which can only
+    /// happen right after EI, the interrupt returns to the HALT, which then executes again.
+    fn halt(&mut self) {
+        if interrupt::pending(&mut self.bus) == 0 {
+            self.mode = Mode::Halted;
+        } else if self.ime {
+            self.pc = self.pc.wrapping_sub(1);
+        } else {
+            self.halt_bug = true;
+        }
+    }
+    /// Implements the 11 illegal opcodes, which hang the CPU with PC just past the opcode
+    fn lock(&mut self, opcode: u8) {
+        self.mode = Mode::Locked;
+        if self.illegal_policy == IllegalPolicy::Report {
+            let pc = self.pc.wrapping_sub(1);
+            self.illegal_opcode = Some(IllegalOpcode {
+                opcode,
+                bank: self.bus.bank(pc),
+                pc,
+            });
+        }
+    }
+    /// Pushes PC and jumps to the vector of the highest priority pending interrupt
+    /// The vector is only chosen after the high byte of PC is pushed. If that push overwrote
+    /// IE and cancelled every pending interrupt, the CPU ends up at 0x0000 instead.
+    /// Takes two internal m-cycles, the two pushes and one more to set PC.
+    fn dispatch_interrupt(&mut self) {
+        self.ime = false;
+        self.internal_cycle();
+        self.internal_cycle();
+        let (high, low) = u16_to_u8s(self.pc);
+        self.sp = self.sp.wrapping_sub(1);
+        let sp = self.sp;
+        self.write_cycle(sp, high);
+        let pending = interrupt::pending(&mut self.bus);
+        self.sp = self.sp.wrapping_sub(1);
+        let sp = self.sp;
+        self.write_cycle(sp, low);
+        self.pc = match Interrupt::highest(pending) {
+            Some(interrupt) => {
+                let flags = self.bus.read(interrupt::IF);
+                self.bus.write(interrupt::IF, flags & !interrupt.bit());
+                interrupt.vector()
+            }
+            None => 0x0000,
+        };
+        self.internal_cycle();
+    }
+    /// Executes the instruction at PC from the block cache, if it is on and PC is cacheable
+    /// Returns whether it did. Each byte of the instruction still takes its m-cycle, it is
+    /// just not read back from the bus.
+    fn cached(&mut self) -> bool {
+        let fetched = match self.cache {
+            Some(ref mut cache) => cache.fetch(&mut self.bus, self.pc),
+            None => None,
+        };
+        match fetched {
+            Some((instruction, length)) => {
+                for _ in 0..length {
+                    self.internal_cycle();
+                }
+                self.pc = self.pc.wrapping_add(length);
+                self.execute(instruction);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Reads the byte at PC in one m-cycle and advances PC past it
+    fn next8(&mut self) -> u8 {
+        let pc = self.pc;
+        let value = self.read_cycle(pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+    /// Executes a single decoded instruction whose bytes have all been fetched
+    /// Every memory access and internal delay of the instruction ticks the bus as it happens
+    fn execute(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Nop => {}
+            Instruction::Stop => {
+                // STOP resets DIV, its padding byte having been fetched like an immediate
+                self.bus.write(DIV, 0);
+                self.mode = Mode::Stopped;
+            }
+            Instruction::Halt => self.halt(),
+            Instruction::Di => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            }
+            Instruction::Ei => self.ime_scheduled = true,
+            Instruction::Ld(to, from) => self.load(to, from),
+            Instruction::Ld16(register, value) => self.set16(register, value),
+            Instruction::LdNnSp(address) => {
+                let sp = self.sp;
+                self.write16(Operand::Abs(address), sp);
+            }
+            Instruction::LdSpHl => {
+                self.sp = self.fetch16(R16::HL);
+                self.internal_cycle();
+            }
+            Instruction::LdHlSp(offset) => self.load_hl_sp(offset),
+            Instruction::Push(register) => self.push(register),
+            Instruction::Pop(register) => self.pop(register),
+            Instruction::Alu(op, operand) => self.alu(op, operand),
+            Instruction::Inc(operand) => self.inc(operand),
+            Instruction::Dec(operand) => self.dec(operand),
+            Instruction::Inc16(register) => self.inc16(register),
+            Instruction::Dec16(register) => self.dec16(register),
+            Instruction::AddHl(register) => self.add_hl(register),
+            Instruction::AddSp(offset) => self.add_sp(offset),
+            Instruction::RotateA(op) => self.rotate_a(op),
+            Instruction::Daa => self.daa(),
+            Instruction::Cpl => self.cpl(),
+            Instruction::Scf | Instruction::Ccf => {
+                self.flags.carry = instruction == Instruction::Scf || !self.flags.carry;
+                self.flags.subtract = false;
+                self.flags.half_carry = false;
+            }
+            Instruction::Jp(condition, address) => {
+                self.jump(condition, address);
+            }
+            Instruction::JpHl => self.pc = self.fetch16(R16::HL),
+            Instruction::Jr(condition, offset) => {
+                self.jump_relative(condition, offset);
+            }
+            Instruction::Call(condition, address) => {
+                self.call(condition, address);
+            }
+            Instruction::Ret(condition) => {
+                self.ret(condition);
+            }
+            Instruction::Reti => self.reti(),
+            Instruction::Rst(vector) => self.rst(vector),
+            Instruction::Shift(op, operand) => {
+                let value = self.read8(operand);
+                let res = self.shift(op, value);
+                self.write8(operand, res);
+            }
+            Instruction::Bit(bit, operand) => {
+                // BIT only reads its operand, so (HL) costs one access less than RES and SET
+                let value = self.read8(operand);
+                self.flags.zero = value & (1 << bit) == 0;
+                self.flags.subtract = false;
+                self.flags.half_carry = true;
+            }
+            Instruction::Res(bit, operand) => {
+                let value = self.read8(operand);
+                self.write8(operand, value & !(1 << bit));
+            }
+            Instruction::Set(bit, operand) => {
+                let value = self.read8(operand);
+                self.write8(operand, value | (1 << bit));
+            }
+            Instruction::Illegal(opcode) => self.lock(opcode),
+        }
+    }
+    /// USAGE: self.check(COND) where COND is the condition to test
+    /// Returns whether COND holds for the current flags
+    pub fn check(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Always => true,
+            Condition::NZ => !self.flags.zero,
+            Condition::Z => self.flags.zero,
+            Condition::NC => !self.flags.carry,
+            Condition::C => self.flags.carry,
+        }
+    }
+    /// USAGE: self.push(R) where R is the 16-bit register to push
+    /// Implements PUSH rr instruction, an internal m-cycle followed by the two writes
+    pub fn push(&mut self, register: R16) {
+        let value = self.fetch16(register);
+        self.push16(value);
+    }
+    /// USAGE: self.pop(R) where R is the 16-bit register to pop into
+    /// Implements POP rr instruction, two reads with no internal m-cycle
+    pub fn pop(&mut self, register: R16) {
+        let value = self.pop16();
+        self.set16(register, value);
+    }
+    /// USAGE: self.jump(COND, NN) where NN is the address to jump to
+    /// Implements JP cc,nn and JP nn instructions
+    /// A taken jump spends one internal m-cycle loading PC, making it 4 m-cycles against 3
+    /// Returns whether the jump was taken
+    pub fn jump(&mut self, condition: Condition, address: u16) -> bool {
+        let taken = self.check(condition);
+        if taken {
+            self.pc = address;
+            self.internal_cycle();
+        }
+        taken
+    }
+    /// USAGE: self.jump_relative(COND, E) where E is the signed offset to add to PC
+    /// Implements JR cc,e and JR e instructions
+    /// A taken jump spends one internal m-cycle adding E, making it 3 m-cycles against 2
+    /// Returns whether the jump was taken
+    pub fn jump_relative(&mut self, condition: Condition, offset: i8) -> bool {
+        let taken = self.check(condition);
+        if taken {
+            self.pc = self.pc.wrapping_add(offset as u16);
+            self.internal_cycle();
+        }
+        taken
+    }
+    /// USAGE: self.call(COND, NN) where NN is the address of the subroutine
+    /// Implements CALL cc,nn and CALL nn instructions
+    /// A taken call pushes PC like PUSH does, making it 6 m-cycles against 3
+    /// Returns whether the call was taken
+    pub fn call(&mut self, condition: Condition, address: u16) -> bool {
+        let taken = self.check(condition);
+        if taken {
+            let pc = self.pc;
+            self.push16(pc);
+            self.pc = address;
+        }
+        taken
+    }
+    /// USAGE: self.ret(COND)
+    /// Implements RET cc and RET instructions
+    /// RET takes 4 m-cycles, while RET cc spends an extra internal m-cycle checking COND,
+    /// taking 5 if taken and 2 if not
+    /// Returns whether the return was taken
+    pub fn ret(&mut self, condition: Condition) -> bool {
+        if condition != Condition::Always {
+            self.internal_cycle();
+        }
+        let taken = self.check(condition);
+        if taken {
+            self.pc = self.pop16();
+            self.internal_cycle();
+        }
+        taken
+    }
+    /// USAGE: self.reti()
+    /// Implements RETI instruction, which returns and enables interrupts without EI's delay
+    pub fn reti(&mut self) {
+        self.pc = self.pop16();
+        self.internal_cycle();
+        self.ime = true;
+    }
+    /// USAGE: self.rst(N) where N is one of 0x00, 0x08, .. 0x38
+    /// Implements RST n instruction, a one byte call to N taking 4 m-cycles
+    pub fn rst(&mut self, vector: u8) {
+        let pc = self.pc;
+        self.push16(pc);
+        self.pc = vector as u16;
+    }
+    /// Pushes VALUE high byte first, after the internal m-cycle spent decrementing SP
+    fn push16(&mut self, value: u16) {
+        let (high, low) = u16_to_u8s(value);
+        self.internal_cycle();
+        self.sp = self.sp.wrapping_sub(1);
+        let sp = self.sp;
+        self.write_cycle(sp, high);
+        self.sp = self.sp.wrapping_sub(1);
+        let sp = self.sp;
+        self.write_cycle(sp, low);
+    }
+    /// Pops a value low byte first, one m-cycle per byte
+    fn pop16(&mut self) -> u16 {
+        let sp = self.sp;
+        let low = self.read_cycle(sp);
+        self.sp = self.sp.wrapping_add(1);
+        let sp = self.sp;
+        let high = self.read_cycle(sp);
+        self.sp = self.sp.wrapping_add(1);
+        u8s_to_u16(high, low)
+    }
+    /// Returns SP plus a signed OFFSET, as used by ADD SP,e and LD HL,SP+e
+    /// Flags come from the unsigned addition of OFFSET to the low byte of SP
+    fn offset_sp(&mut self, offset: i8) -> u16 {
+        let (sp, low) = (self.sp, offset as u8 as u16);
+        self.flags = Flags {
+            zero: false,
+            subtract: false,
+            half_carry: (sp & 0x0F) + (low & 0x0F) > 0x0F,
+            carry: (sp & 0xFF) + low > 0xFF,
+        };
+        sp.wrapping_add(offset as u16)
+    }
+    /// Implements RLCA, RRCA, RLA and RRA
+    /// These behave like RLC A, RRC A, RL A and RR A, except that Z is always cleared
+    fn rotate_a(&mut self, op: Shift) {
+        let a = self.fetch8(R8::A);
+        let res = self.shift(op, a);
+        self.set8(R8::A, res);
+        self.flags.zero = false;
+    }
+    /// Implements the eight CB-prefixed shift operations:
+    /// RLC, RRC, RL, RR, SLA, SRA, SWAP and SRL
+    /// Returns the shifted value, setting Z from it and C from the bit shifted out
+    fn shift(&mut self, op: Shift, value: u8) -> u8 {
+        let carry_in = self.flags.carry as u8;
+        let (res, carry) = match op {
+            Shift::Rlc => (value.rotate_left(1), value & 0x80 != 0),
+            Shift::Rrc => (value.rotate_right(1), value & 0x01 != 0),
+            Shift::Rl => ((value << 1) | carry_in, value & 0x80 != 0),
+            Shift::Rr => ((value >> 1) | (carry_in << 7), value & 0x01 != 0),
+            Shift::Sla => (value << 1, value & 0x80 != 0),
+            Shift::Sra => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            Shift::Swap => (value.rotate_left(4), false),
+            Shift::Srl => (value >> 1, value & 0x01 != 0),
+        };
+        self.flags = Flags {
+            zero: res == 0,
+            subtract: false,
+            half_carry: false,
+            carry,
+        };
+        res
+    }
+    /// Implements the eight accumulator operations:
+    /// ADD, ADC, SUB, SBC, AND, XOR, OR and CP
+    fn alu(&mut self, op: Alu, operand: Operand) {
+        match op {
+            Alu::Add => self.add8(R8::A, operand),
+            Alu::Adc => self.adc(operand),
+            Alu::Sub => self.sub(operand),
+            Alu::Sbc => self.sbc(operand),
+            Alu::And => self.and(operand),
+            Alu::Xor => self.xor(operand),
+            Alu::Or => self.or(operand),
+            Alu::Cp => self.cp(operand),
         }
-        self.flags.half_carry = detect_half_carry(i, j);
     }
 }
 
+/// Number of t-cycles in one frame of 154 scanlines
+pub const CYCLES_PER_FRAME: u64 = 70224;
+
+/// A monotonic timestamp, counting t-cycles since power on
+/// At 4 MiHz a u64 lasts for well over a hundred thousand years
 struct Clock {
-    m: u8,
-    t: u8,
+    t: u64,
 }
 
+/// The four flags kept in the high nibble of the F register
+/// The low nibble of F does not exist in hardware and always reads as zero
 struct Flags {
-    add: bool,
+    zero: bool,
+    subtract: bool,
     carry: bool,
     half_carry: bool,
 }
 
+impl Flags {
+    const ZERO: u8 = 0x80;
+    const SUBTRACT: u8 = 0x40;
+    const HALF_CARRY: u8 = 0x20;
+    const CARRY: u8 = 0x10;
+
+    /// USAGE: self.flags.to_byte()
+    /// Returns the flags packed as the F register, ZNHC from bit 7 down to bit 4
+    fn to_byte(&self) -> u8 {
+        let mut f = 0;
+        if self.zero {
+            f |= Flags::ZERO;
+        }
+        if self.subtract {
+            f |= Flags::SUBTRACT;
+        }
+        if self.half_carry {
+            f |= Flags::HALF_CARRY;
+        }
+        if self.carry {
+            f |= Flags::CARRY;
+        }
+        f
+    }
+    /// USAGE: Flags::from_byte(F) where F is a value written to the F register
+    /// Unpacks the flags from F, dropping the low nibble
+    fn from_byte(f: u8) -> Self {
+        Flags {
+            zero: f & Flags::ZERO != 0,
+            subtract: f & Flags::SUBTRACT != 0,
+            half_carry: f & Flags::HALF_CARRY != 0,
+            carry: f & Flags::CARRY != 0,
+        }
+    }
+}
+
+/// Address of the joypad register, whose low nibble holds the input lines
+const P1: u16 = 0xFF00;
+/// Address of the divider register, which is reset by any write
+const DIV: u16 = 0xFF04;
+
 pub fn u8s_to_u16(high: u8, low: u8) -> u16 {
     let high = (high as u16) << 8;
     high + (low as u16)
@@ -170,6 +1084,7 @@ pub fn u16_to_u8s(wbyte: u16) -> (u8, u8) {
     ((wbyte >> 8) as u8, wbyte as u8)
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum R8 {
     A,
@@ -186,14 +1101,41 @@ use std::slice::Iter;
 impl R8 {
     pub fn registers() -> Iter<'static, R8> {
         static REGISTERS: [R8; 7] = [R8::A, R8::B, R8::C, R8::D, R8::E, R8::H, R8::L];
-        REGISTERS.into_iter()
+        REGISTERS.iter()
     }
 }
 
-#[derive(Clone, Copy)]
+/// An 8-bit operand, either a register or constant, or a location in memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// A register or an 8-bit constant
+    Reg(R8),
+    /// The address held in a 16-bit register, e.g. (HL)
+    Ind(R16),
+    /// (HL+), the address in HL which is incremented after the access
+    HlInc,
+    /// (HL-), the address in HL which is decremented after the access
+    HlDec,
+    /// (n), an address in the high page 0xFF00-0xFFFF as used by LDH
+    High(u8),
+    /// (C), the address 0xFF00 + C
+    HighC,
+    /// (nn), an absolute address
+    Abs(u16),
+}
+
+impl From<R8> for Operand {
+    fn from(register: R8) -> Self {
+        Operand::Reg(register)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum R16 {
     PC,
     SP,
+    AF,
     BC,
     DE,
     HL,
@@ -202,45 +1144,138 @@ pub enum R16 {
 
 impl R16 {
     pub fn registers() -> Iter<'static, R16> {
-        static REGISTERS: [R16; 5] = [R16::PC, R16::SP, R16::BC, R16::DE, R16::HL];
-        REGISTERS.into_iter()
+        static REGISTERS: [R16; 6] = [R16::PC, R16::SP, R16::AF, R16::BC, R16::DE, R16::HL];
+        REGISTERS.iter()
     }
 }
 
-fn detect_half_carry(fst: u8, snd: u8) -> bool {
-    let (fst, snd) = ((fst >> 4), (snd >> 4));
-    (fst & snd) != 0
+/// A branch condition, tested against the zero and carry flags
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Always,
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+/// Returns whether FST + SND + CARRY carries out of bit 3
+fn detect_half_carry(fst: u8, snd: u8, carry: bool) -> bool {
+    (fst & 0x0F) + (snd & 0x0F) + (carry as u8) > 0x0F
+}
+
+/// Returns whether FST - SND - CARRY borrows from bit 4
+fn detect_half_borrow(fst: u8, snd: u8, carry: bool) -> bool {
+    (fst & 0x0F) < (snd & 0x0F) + (carry as u8)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+
+    /// A memory access made through the bus, as recorded by LoggingBus
+    #[derive(Debug, PartialEq)]
+    enum Access {
+        Read(u16),
+        Write(u16, u8),
+    }
+
+    /// A flat 64 KiB bus recording every access along with the m-cycle it happened in
+    /// TIMA reads back the number of m-cycles ticked so far, standing in for a timer
+    struct LoggingBus {
+        mem: [u8; 0x10000],
+        cycle: u64,
+        log: Vec<Logged>,
+    }
+
+    impl LoggingBus {
+        fn new() -> Self {
+            LoggingBus {
+                mem: [0; 0x10000],
+                cycle: 0,
+                log: Vec::new(),
+            }
+        }
+        /// Returns the accesses made outside IE and IF, which the CPU polls for free
+        fn accesses(&self) -> Vec<&Logged> {
+            self.log
+                .iter()
+                .filter(|&(_, access)| match *access {
+                    Access::Read(a) | Access::Write(a, _) => {
+                        a != interrupt::IE && a != interrupt::IF
+                    }
+                })
+                .collect()
+        }
+    }
+
+    impl Bus for LoggingBus {
+        fn read(&mut self, address: u16) -> u8 {
+            self.log.push((self.cycle, Access::Read(address)));
+            if address == TIMA {
+                self.cycle as u8
+            } else {
+                self.mem[address as usize]
+            }
+        }
+        fn write(&mut self, address: u16, value: u8) {
+            self.log.push((self.cycle, Access::Write(address, value)));
+            self.mem[address as usize] = value;
+        }
+        fn tick(&mut self) {
+            self.cycle += 1;
+        }
+    }
+
+    const TIMA: u16 = 0xFF05;
+
+    type Logged = (u64, Access);
+
     #[test]
     fn can_detect_half_carry() {
-        use std::u8::MAX;
-        for i in 0..MAX {
-            for j in 0..MAX {
-                // Represent each number as an 8-bit string formatted 0bXXXXXXXX
-                let (is, js) = (format!("{:#010b}", i), format!("{:#010b}", j));
-                let mut half_carry = false;
-                // Indices 2 .. 6 are the high nibble of each byte
-                for k in 2..6 {
-                    let (n, m) = (is.chars().nth(k).unwrap(), js.chars().nth(k).unwrap());
-                    // If any bit is equal, there will be a carry in the high nibble
-                    if n == '1' && m == '1' {
-                        half_carry = true;
+        for i in 0..u8::MAX {
+            for j in 0..u8::MAX {
+                for &carry in &[false, true] {
+                    // Represent each number as an 8-bit string formatted 0bXXXXXXXX
+                    let (is, js) = (format!("{:#010b}", i), format!("{:#010b}", j));
+                    let mut half_carry = carry;
+                    // Indices 6 .. 10 are the low nibble of each byte, ripple carry through
+                    // them from the lowest bit up
+                    for k in (6..10).rev() {
+                        let (n, m) = (is.chars().nth(k).unwrap(), js.chars().nth(k).unwrap());
+                        let ones = (n == '1') as u8 + (m == '1') as u8 + half_carry as u8;
+                        half_carry = ones >= 2;
                     }
+                    // Whatever carries out of bit 3 is the half carry
+                    assert_eq!(detect_half_carry(i, j, carry), half_carry);
+                }
+            }
+        }
+    }
+    #[test]
+    fn can_detect_half_borrow() {
+        for i in 0..u8::MAX {
+            for j in 0..u8::MAX {
+                for &carry in &[false, true] {
+                    let (is, js) = (format!("{:#010b}", i), format!("{:#010b}", j));
+                    let mut half_borrow = carry;
+                    // Ripple the borrow through the low nibble from the lowest bit up
+                    for k in (6..10).rev() {
+                        let (n, m) = (is.chars().nth(k).unwrap(), js.chars().nth(k).unwrap());
+                        let diff = (n == '1') as i8 - (m == '1') as i8 - half_borrow as i8;
+                        half_borrow = diff < 0;
+                    }
+                    // Whatever borrows into bit 3 from bit 4 is the half carry of a subtraction
+                    assert_eq!(detect_half_borrow(i, j, carry), half_borrow);
                 }
-                // Detect half carry should determine the same as the above, using bit shifts
-                assert_eq!(detect_half_carry(i, j), half_carry);
             }
         }
     }
     // Checks for all u16s that splitting and recombining results in the same number
     #[test]
     fn u16_splitting_and_combining_rational() {
-        use std::u16::MAX;
-        for i in 0..MAX {
+        for i in 0..u16::MAX {
             let (high, low) = u16_to_u8s(i);
             assert_eq!(i, u8s_to_u16(high, low));
         }
@@ -248,35 +1283,205 @@ mod test {
     // Checks for all pairs of u8s that combining and splitting results in the same numbers back
     #[test]
     fn u8_combining_and_splitting_rational() {
-        use std::u8::MAX;
-        for i in 0..MAX {
-            for j in 0..MAX {
+        for i in 0..u8::MAX {
+            for j in 0..u8::MAX {
                 let combined = u8s_to_u16(i, j);
                 assert_eq!((i, j), u16_to_u8s(combined));
             }
         }
     }
-    // Checks that ticking properly updates the clock and zeroes out M and T
+    // Checks that every m-cycle ticks the bus once and moves the clock 4 t-cycles
     #[test]
     fn cpu_can_tick() {
-        let mut cpu = CPU::new();
-        let (old_m, old_t) = (cpu.clock.m, cpu.clock.t);
-        cpu.tick(1); // Set M register to 1 and T register to 2
-        cpu.update_clock(); // add M and T registers to clock.m and clock.t, then zero out M and T
-        assert_eq!((cpu.m, cpu.t), (0, 0));
-        let (diff_m, diff_t) = (cpu.clock.m - old_m, cpu.clock.t - old_t);
-        assert_eq!(diff_t, 4); // t clock increases 4 per tick
-        assert_eq!(diff_m, 1); // m clock increases 1 per tick
+        let mut cpu = CPU::new(LoggingBus::new());
+        let old_t = cpu.cycles();
+        cpu.internal_cycle();
+        assert_eq!(cpu.bus.cycle, 1);
+        cpu.read_cycle(0x1234);
+        cpu.write_cycle(0x1234, 0x56);
+        assert_eq!(cpu.bus.cycle, 3);
+        assert_eq!(cpu.bus.log, vec![(2, Access::Read(0x1234)), (3, Access::Write(0x1234, 0x56))]);
+        let diff_t = cpu.cycles() - old_t;
+        assert_eq!(diff_t, 12); // t clock increases 4 per tick
+    }
+    // Checks that stepping accumulates time well past what fits in a u8 or a single frame
+    #[test]
+    fn cpu_clock_keeps_counting_across_frames() {
+        let mut mem = [0u8; 0x10000];
+        mem[0x1000] = 0x18; // JR -2
+        mem[0x1001] = 0xFE;
+        let mut cpu = CPU::new(mem);
+        cpu.pc = 0x1000;
+        for _ in 0..100_000 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.cycles(), 1_200_000);
+        assert_eq!(cpu.frame_cycles(), 1_200_000 % 70224);
+        // HALT and interrupt dispatch count just the same
+        let mut mem = [0u8; 0x10000];
+        mem[0] = 0x76;
+        mem[0xFFFF] = 0x01;
+        let mut cpu = CPU::new(mem);
+        cpu.sp = 0xD000;
+        cpu.ime = true;
+        for _ in 0..9 {
+            cpu.step().unwrap();
+        }
+        cpu.bus[0xFF0F] = 0x01;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles(), 4 * (1 + 8 + 1 + 5));
+    }
+    // Checks that the accesses of an instruction land on the m-cycle hardware makes them in
+    #[test]
+    fn cpu_accesses_memory_on_the_right_m_cycle() {
+        use self::Access::*;
+        let programs: [(&[u8], &[Logged]); 6] = [
+            // INC (HL): fetch, read, write
+            (&[0x34], &[(1, Read(0x100)), (2, Read(0xC000)), (3, Write(0xC000, 0x01))]),
+            // PUSH BC: fetch, internal, high byte, low byte
+            (&[0xC5], &[(1, Read(0x100)), (3, Write(0xCFFF, 0x00)), (4, Write(0xCFFE, 0x00))]),
+            // RET: fetch, low byte, high byte, internal
+            (&[0xC9], &[(1, Read(0x100)), (2, Read(0xD000)), (3, Read(0xD001))]),
+            // LD (nn),SP: fetch, operand, operand, low byte, high byte
+            (
+                &[0x08, 0x00, 0xC1],
+                &[
+                    (1, Read(0x100)),
+                    (2, Read(0x101)),
+                    (3, Read(0x102)),
+                    (4, Write(0xC100, 0x00)),
+                    (5, Write(0xC101, 0xD0)),
+                ],
+            ),
+            // CALL nn: fetch, operand, operand, internal, high byte, low byte
+            (
+                &[0xCD, 0x00, 0x20],
+                &[
+                    (1, Read(0x100)),
+                    (2, Read(0x101)),
+                    (3, Read(0x102)),
+                    (5, Write(0xCFFF, 0x01)),
+                    (6, Write(0xCFFE, 0x03)),
+                ],
+            ),
+            // BIT 0,(HL): fetch, fetch, read and no write back
+            (&[0xCB, 0x46], &[(1, Read(0x100)), (2, Read(0x101)), (3, Read(0xC000))]),
+        ];
+        for &(program, expected) in &programs {
+            let mut bus = LoggingBus::new();
+            bus.mem[0x100..0x100 + program.len()].copy_from_slice(program);
+            let mut cpu = CPU::new(bus);
+            cpu.pc = 0x100;
+            cpu.sp = 0xD000;
+            cpu.set16(R16::HL, 0xC000);
+            let cycles = cpu.step().unwrap();
+            assert_eq!(cpu.bus.accesses(), expected.iter().collect::<Vec<_>>());
+            // Internal m-cycles still tick the bus, including any after the last access
+            assert_eq!(cpu.bus.cycle, cycles as u64);
+        }
+    }
+    // Checks that interrupt dispatch pushes PC on its third and fourth m-cycles
+    #[test]
+    fn cpu_dispatches_interrupts_on_the_right_m_cycle() {
+        let mut bus = LoggingBus::new();
+        bus.mem[interrupt::IE as usize] = 0x04;
+        bus.mem[interrupt::IF as usize] = 0x04;
+        let mut cpu = CPU::new(bus);
+        cpu.pc = 0x1234;
+        cpu.sp = 0xD000;
+        cpu.ime = true;
+        assert_eq!(cpu.step(), Ok(5));
+        assert_eq!(
+            cpu.bus.accesses(),
+            vec![&(3, Access::Write(0xCFFF, 0x12)), &(4, Access::Write(0xCFFE, 0x34))]
+        );
+        assert_eq!((cpu.bus.cycle, cpu.pc), (5, 0x0050));
+    }
+    // Checks that a register read in the middle of an instruction sees the hardware as it is
+    // on that m-cycle rather than as it was when the instruction started
+    #[test]
+    fn cpu_reads_mid_instruction_state() {
+        // LD A,(HL) reads on its 2nd m-cycle, LDH A,(n) on its 3rd, LD A,(nn) on its 4th
+        let programs: [(&[u8], u8); 3] = [
+            (&[0x7E], 2),
+            (&[0xF0, 0x05], 3),
+            (&[0xFA, 0x05, 0xFF], 4),
+        ];
+        for &(program, expected) in &programs {
+            let mut bus = LoggingBus::new();
+            bus.mem[..program.len()].copy_from_slice(program);
+            let mut cpu = CPU::new(bus);
+            cpu.set16(R16::HL, TIMA);
+            cpu.step().unwrap();
+            assert_eq!(cpu.fetch8(R8::A), expected);
+        }
+    }
+    // Checks that every legal opcode ticks the bus exactly once per m-cycle it reports
+    #[test]
+    fn cpu_ticks_bus_once_per_m_cycle() {
+        let illegal = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+        for opcode in 0..=u8::MAX {
+            if illegal.contains(&opcode) {
+                continue;
+            }
+            for &f in &[0x00u16, 0xF0] {
+                let mut bus = LoggingBus::new();
+                bus.mem[0x100..0x103].copy_from_slice(&[opcode, 0x46, 0xC0]);
+                let mut cpu = CPU::new(bus);
+                cpu.pc = 0x100;
+                cpu.sp = 0xD000;
+                cpu.set16(R16::AF, f);
+                cpu.set16(R16::HL, 0xC000);
+                let cycles = cpu.step().unwrap();
+                assert_eq!(cpu.bus.cycle, cycles as u64, "{:#04X}", opcode);
+                assert_eq!(cpu.cycles(), 4 * cycles as u64, "{:#04X}", opcode);
+            }
+        }
+    }
+    // Checks that a register snapshot survives a round trip and prints like a trace log
+    #[test]
+    fn cpu_registers_round_trip() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        let mut regs = cpu.registers();
+        assert_eq!(regs.to_string(), "A:00 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0000");
+        assert_eq!((regs.ime, regs.mode), (false, Mode::Running));
+        regs.set_af(0x01BF);
+        regs.set_bc(0x0013);
+        regs.set_de(0x00D8);
+        regs.set_hl(0x014D);
+        regs.sp = 0xFFFE;
+        regs.pc = 0x0100;
+        assert_eq!((regs.af(), regs.bc(), regs.de(), regs.hl()), (0x01B0, 0x13, 0xD8, 0x014D));
+        cpu.set_registers(&regs);
+        assert_eq!(cpu.registers(), regs);
+        assert_eq!(cpu.fetch16(R16::AF), 0x01B0);
+        assert_eq!(cpu.fetch8(R8::L), 0x4D);
+        assert_eq!(regs.to_string(), "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100");
+        // The low nibble of F cannot be set, even directly
+        regs.f = 0xFF;
+        cpu.set_registers(&regs);
+        assert_eq!(cpu.registers().f, 0xF0);
+        // Execution shows up in the next snapshot, halt state included
+        cpu.bus[0x0100] = 0x76;
+        cpu.step().unwrap();
+        let halted = cpu.registers();
+        assert_eq!((halted.pc, halted.mode), (0x0101, Mode::Halted));
+        assert_ne!(halted, regs);
+        regs.ime = true;
+        regs.mode = Mode::Running;
+        cpu.set_registers(&regs);
+        assert!(cpu.ime);
+        assert_eq!(cpu.mode(), Mode::Running);
     }
     // Checks that setting any 8-bit registers with any u8 value will return the same result when fetched
     #[test]
     fn cpu_can_fetch_and_set_8bit_registers() {
-        use std::u8::MAX;
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new([0u8; 0x10000]);
         for reg in R8::registers() {
-            for i in 0..MAX {
+            for i in 0..u8::MAX {
                 cpu.set8(*reg, i);
-                assert_eq!((2, 8), (cpu.m, cpu.t)); // LD r, n should take 2 m-cycles
+                assert_eq!(cpu.cycles(), 0); // Only memory accesses take time, not registers
                 assert_eq!(cpu.fetch8(*reg), i);
             }
         }
@@ -285,17 +1490,27 @@ mod test {
     // fetched
     #[test]
     fn cpu_can_fetch_and_set_16bit_registers() {
-        use std::u16::MAX;
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new([0u8; 0x10000]);
         for reg in R16::registers() {
-            for i in 0..MAX {
+            for i in 0..u16::MAX {
                 cpu.set16(*reg, i);
-                assert_eq!((3, 12), (cpu.m, cpu.t)); // LD rr, nn should take 3 m-cycles
-                assert_eq!(cpu.fetch16(*reg), i);
+                assert_eq!(cpu.cycles(), 0); // Only memory accesses take time, not registers
                 match *reg {
                     R16::PC | R16::SP => {
+                        assert_eq!(cpu.fetch16(*reg), i);
                         continue;
                     }
+                    R16::AF => {
+                        // The low nibble of F always reads as zero
+                        assert_eq!(cpu.fetch16(*reg), i & 0xFFF0);
+                        let (high, _) = u16_to_u8s(i);
+                        assert_eq!(cpu.fetch8(R8::A), high);
+                        continue;
+                    }
+                    _ => assert_eq!(cpu.fetch16(*reg), i),
+                }
+                match *reg {
+                    R16::PC | R16::SP | R16::AF => {}
                     R16::BC => {
                         let (high, low) = u16_to_u8s(i);
                         assert_eq!(cpu.fetch8(R8::B), high);
@@ -319,17 +1534,17 @@ mod test {
     // Checks that loading any register to any other register with some u8 will properly set it
     #[test]
     fn cpu_can_load_registers_to_registers() {
-        use std::u8::MAX;
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new([0u8; 0x10000]);
         for from in R8::registers() {
             for to in R8::registers() {
-                for i in 0..MAX {
-                    for j in 0..MAX {
+                for i in 0..u8::MAX {
+                    for j in 0..u8::MAX {
                         cpu.set8(*from, i);
                         cpu.set8(*to, j);
                         cpu.load(*to, *from);
                         assert_eq!(cpu.fetch8(*to), cpu.fetch8(*from));
-                        assert_eq!((1, 4), (cpu.m, cpu.t)); // LD r, r should take 1 M cycle
+                        // A register-to-register load takes no bus cycles on its own
+                        assert_eq!(cpu.cycles(), 0);
                         if from == to {
                             // If from == to, then to == j, and NOT i, since to = from == to = j
                             assert_eq!(cpu.fetch8(*to), j);
@@ -346,11 +1561,10 @@ mod test {
     // register
     #[test]
     fn cpu_can_add_8_bit_registers() {
-        use std::u8::MAX;
-        let max = MAX as u16;
-        let mut cpu = CPU::new();
-        for i in 0..MAX {
-            for j in 0..MAX {
+        let max = u8::MAX as u16;
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for i in 0..u8::MAX {
+            for j in 0..u8::MAX {
                 for reg1 in R8::registers() {
                     for reg2 in R8::registers() {
                         cpu.set8(*reg1, i);
@@ -358,16 +1572,18 @@ mod test {
                         let i = cpu.fetch8(*reg1);
                         let j = cpu.fetch8(*reg2);
                         cpu.add8(*reg1, *reg2);
-                        assert!(cpu.flags.add);
+                        assert!(!cpu.flags.subtract);
                         let res = (i as u16) + (j as u16);
-                        if res >= max {
+                        if res > max {
                             let (_, low) = u16_to_u8s(res);
                             assert!(cpu.flags.carry);
                             assert_eq!(cpu.fetch8(*reg1), low);
                         } else {
+                            assert!(!cpu.flags.carry);
                             assert_eq!(cpu.fetch8(*reg1), i + j);
                         }
-                        assert_eq!(cpu.flags.half_carry, detect_half_carry(i, j));
+                        assert_eq!(cpu.flags.zero, cpu.fetch8(*reg1) == 0);
+                        assert_eq!(cpu.flags.half_carry, detect_half_carry(i, j, false));
                     }
                 }
             }
@@ -375,10 +1591,8 @@ mod test {
     }
     #[test]
     fn cpu_can_add_constants_to_registers() {
-        use std::u8::MAX as MAX8;
-        use std::u16::MAX as MAX16;
-        let mut cpu = CPU::new();
-        for i in 0..MAX8 {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for i in 0..u8::MAX {
             for reg in R8::registers() {
                 cpu.set8(*reg, 0);
                 cpu.add8(*reg, R8::CONST(i));
@@ -386,4 +1600,811 @@ mod test {
             }
         } 
     }
+    // Runs a short program that loads, adds and stores through (HL), checking every step
+    #[test]
+    fn cpu_can_step_through_a_program() {
+        let mut mem = [0u8; 0x10000];
+        let program = [
+            0x3E, 0x05, // LD A,$05
+            0x06, 0x03, // LD B,$03
+            0x80, // ADD A,B
+            0x21, 0x00, 0xC0, // LD HL,$C000
+            0x77, // LD (HL),A
+            0x2C, // INC L
+            0x36, 0x42, // LD (HL),$42
+            0x7E, // LD A,(HL)
+            0x3D, // DEC A
+        ];
+        mem[..program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(mem);
+        let cycles: Vec<u8> = (0..9).map(|_| cpu.step().unwrap()).collect();
+        assert_eq!(cycles, vec![2, 2, 1, 3, 2, 1, 3, 2, 1]);
+        assert_eq!(cpu.pc as usize, program.len());
+        assert_eq!(cpu.bus[0xC000], 0x08);
+        assert_eq!(cpu.bus[0xC001], 0x42);
+        assert_eq!(cpu.fetch8(R8::A), 0x41);
+        assert_eq!(cpu.fetch16(R16::HL), 0xC001);
+        assert!(cpu.flags.subtract); // DEC is a subtraction
+    }
+    // Checks that CALL and RET go through the stack, and that POP AF clears the low nibble of F
+    #[test]
+    fn cpu_can_call_and_return() {
+        let mut mem = [0u8; 0x10000];
+        let program = [
+            0x31, 0xFE, 0xFF, // LD SP,$FFFE
+            0xCD, 0x00, 0x02, // CALL $0200
+            0x00, // NOP
+        ];
+        mem[..program.len()].copy_from_slice(&program);
+        let subroutine = [
+            0x01, 0xFF, 0x12, // LD BC,$12FF
+            0xC5, // PUSH BC
+            0xF1, // POP AF
+            0xC9, // RET
+        ];
+        mem[0x200..0x200 + subroutine.len()].copy_from_slice(&subroutine);
+        let mut cpu = CPU::new(mem);
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Ok(6));
+        assert_eq!((cpu.pc, cpu.sp), (0x0200, 0xFFFC));
+        assert_eq!((cpu.bus[0xFFFD], cpu.bus[0xFFFC]), (0x00, 0x06));
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.fetch8(R8::A), 0x12);
+        assert_eq!(cpu.flags.to_byte(), 0xF0);
+        assert_eq!(cpu.step(), Ok(4));
+        assert_eq!((cpu.pc, cpu.sp), (0x0006, 0xFFFE));
+    }
+    // Checks that conditional jumps only pay for the jump when it is taken
+    #[test]
+    fn cpu_conditional_jumps_take_extra_cycles() {
+        let mut mem = [0u8; 0x10000];
+        mem[..4].copy_from_slice(&[0x20, 0x02, 0x28, 0xFC]); // JR NZ,+2 ; JR Z,-4
+        let mut cpu = CPU::new(mem);
+        cpu.flags.zero = true;
+        assert_eq!(cpu.step(), Ok(2));
+        assert_eq!(cpu.pc, 0x0002);
+        assert_eq!(cpu.step(), Ok(3));
+        assert_eq!(cpu.pc, 0x0000);
+    }
+    // Checks that every legal base opcode executes and takes between 1 and 6 m-cycles
+    #[test]
+    fn cpu_can_execute_every_base_opcode() {
+        let illegal = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+        for opcode in 0..=u8::MAX {
+            if illegal.contains(&opcode) {
+                continue;
+            }
+            let mut mem = [0u8; 0x10000];
+            mem[0x100] = opcode;
+            let mut cpu = CPU::new(mem);
+            cpu.pc = 0x100;
+            let cycles = cpu.step().unwrap();
+            assert!((1..=6).contains(&cycles), "{:#04X} took {} cycles", opcode, cycles);
+        }
+    }
+    // Checks every CB opcode against every operand value, computing expected results the long way
+    #[test]
+    fn cpu_can_execute_every_cb_opcode() {
+        for opcode in 0..=u8::MAX {
+            for value in 0..=u8::MAX {
+                for &carry_in in &[false, true] {
+                    let mut mem = [0u8; 0x10000];
+                    mem[..2].copy_from_slice(&[0xCB, opcode]);
+                    mem[0xC000] = value;
+                    let mut cpu = CPU::new(mem);
+                    cpu.set16(R16::HL, 0xC000);
+                    cpu.set8(R8::B, value);
+                    cpu.set8(R8::A, value);
+                    cpu.flags.carry = carry_in;
+                    let operand = opcode & 0x07;
+                    let flags = cpu.flags.to_byte();
+                    let cycles = cpu.step().unwrap();
+                    let res = match operand {
+                        0 => cpu.fetch8(R8::B),
+                        6 => cpu.bus[0xC000],
+                        7 => cpu.fetch8(R8::A),
+                        _ => continue,
+                    };
+                    let bit = (opcode >> 3) & 0x07;
+                    let wide = value as u16;
+                    let c = carry_in as u16;
+                    let (expected, carry) = match opcode >> 3 {
+                        0 => (((wide << 1) | (wide >> 7)) as u8, wide >> 7 == 1),
+                        1 => (((wide >> 1) | (wide << 7)) as u8, wide & 1 == 1),
+                        2 => (((wide << 1) | c) as u8, wide >> 7 == 1),
+                        3 => (((wide >> 1) | (c << 7)) as u8, wide & 1 == 1),
+                        4 => ((wide << 1) as u8, wide >> 7 == 1),
+                        5 => (((wide >> 1) | (wide & 0x80)) as u8, wide & 1 == 1),
+                        6 => (((wide << 4) | (wide >> 4)) as u8, false),
+                        7 => ((wide >> 1) as u8, wide & 1 == 1),
+                        8..=15 => (value, carry_in),
+                        16..=23 => (value & !(1 << bit), carry_in),
+                        _ => (value | (1 << bit), carry_in),
+                    };
+                    assert_eq!(res, expected, "CB {:02X} on {:02X}", opcode, value);
+                    assert_eq!(cpu.flags.carry, carry, "CB {:02X} on {:02X}", opcode, value);
+                    match opcode >> 6 {
+                        0 => {
+                            assert_eq!(cpu.flags.to_byte() & 0xE0, ((res == 0) as u8) << 7);
+                        }
+                        1 => {
+                            let zero = (value >> bit) & 1 == 0;
+                            assert_eq!(cpu.flags.to_byte() & 0xE0, ((zero as u8) << 7) | 0x20);
+                        }
+                        _ => assert_eq!(cpu.flags.to_byte(), flags),
+                    }
+                    let expected_cycles = match (operand, opcode >> 6) {
+                        (6, 1) => 3,
+                        (6, _) => 4,
+                        _ => 2,
+                    };
+                    assert_eq!(cycles, expected_cycles, "CB {:02X}", opcode);
+                    assert_eq!(cpu.pc, 2);
+                }
+            }
+        }
+    }
+    // Checks that RLCA and friends match their CB counterparts, except for always clearing Z
+    #[test]
+    fn cpu_accumulator_rotates_clear_zero() {
+        for &opcode in &[0x07, 0x0F, 0x17, 0x1F] {
+            for value in 0..=u8::MAX {
+                let mut mem = [0u8; 0x10000];
+                mem[..3].copy_from_slice(&[opcode, 0xCB, opcode]);
+                let mut cpu = CPU::new(mem);
+                cpu.set8(R8::A, value);
+                assert_eq!(cpu.step(), Ok(1));
+                let (a, carry) = (cpu.fetch8(R8::A), cpu.flags.carry);
+                assert!(!cpu.flags.zero);
+                cpu.set8(R8::A, value);
+                cpu.flags.carry = false;
+                cpu.step().unwrap();
+                assert_eq!((a, carry), (cpu.fetch8(R8::A), cpu.flags.carry));
+                assert_eq!(cpu.flags.zero, a == 0);
+            }
+        }
+    }
+    // Checks that each flag lands on its own bit of F, and that F never keeps its low nibble
+    #[test]
+    fn cpu_packs_flags_into_f() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for f in 0..=u8::MAX {
+            cpu.set16(R16::AF, f as u16);
+            assert_eq!(cpu.fetch16(R16::AF), (f & 0xF0) as u16);
+            assert_eq!(cpu.flags.zero, f & 0x80 != 0);
+            assert_eq!(cpu.flags.subtract, f & 0x40 != 0);
+            assert_eq!(cpu.flags.half_carry, f & 0x20 != 0);
+            assert_eq!(cpu.flags.carry, f & 0x10 != 0);
+        }
+        // SUB A,A sets Z and N only
+        cpu.bus[0] = 0x97;
+        cpu.set16(R16::AF, 0x1230);
+        cpu.step().unwrap();
+        assert_eq!(cpu.fetch16(R16::AF), 0x00C0);
+    }
+    // Checks every memory addressing mode against the address it should resolve to
+    #[test]
+    fn cpu_can_load_through_memory_operands() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        cpu.set16(R16::BC, 0xC000);
+        cpu.set16(R16::HL, 0xC010);
+        cpu.set8(R8::C, 0x80);
+        cpu.set8(R8::A, 0x11);
+        cpu.load(Operand::Ind(R16::BC), R8::A);
+        assert_eq!(cpu.bus[0xC080], 0x11);
+        cpu.load(Operand::HighC, R8::CONST(0x22));
+        assert_eq!(cpu.bus[0xFF80], 0x22);
+        cpu.load(Operand::High(0x81), R8::A);
+        assert_eq!(cpu.bus[0xFF81], 0x11);
+        cpu.load(Operand::Abs(0xD000), R8::CONST(0x33));
+        assert_eq!(cpu.bus[0xD000], 0x33);
+        cpu.load(Operand::HlInc, R8::A);
+        assert_eq!((cpu.bus[0xC010], cpu.fetch16(R16::HL)), (0x11, 0xC011));
+        cpu.load(Operand::HlDec, R8::CONST(0x44));
+        assert_eq!((cpu.bus[0xC011], cpu.fetch16(R16::HL)), (0x44, 0xC010));
+        cpu.load(R8::B, Operand::Abs(0xD000));
+        assert_eq!(cpu.fetch8(R8::B), 0x33);
+        cpu.load(R8::D, Operand::HighC);
+        assert_eq!(cpu.fetch8(R8::D), 0x22);
+        cpu.write16(Operand::Abs(0xFFFF), 0xBEEF);
+        assert_eq!((cpu.bus[0xFFFF], cpu.bus[0x0000]), (0xEF, 0xBE));
+        cpu.add8(R8::A, Operand::HlInc);
+        assert_eq!((cpu.fetch8(R8::A), cpu.fetch16(R16::HL)), (0x22, 0xC011));
+    }
+    // Checks the memory forms of LD as executed from opcodes, including LD (nn),SP
+    #[test]
+    fn cpu_can_execute_memory_loads() {
+        let mut mem = [0u8; 0x10000];
+        let program = [
+            0x21, 0x00, 0xC0, // LD HL,$C000
+            0x3E, 0x99, // LD A,$99
+            0x22, // LD (HL+),A
+            0x32, // LD (HL-),A
+            0xE0, 0x80, // LDH ($80),A
+            0x0E, 0x80, // LD C,$80
+            0xF2, // LD A,(C)
+            0x31, 0x34, 0x12, // LD SP,$1234
+            0x08, 0x00, 0xD0, // LD ($D000),SP
+            0xFA, 0x01, 0xD0, // LD A,($D001)
+        ];
+        mem[..program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(mem);
+        let cycles: Vec<u8> = (0..10).map(|_| cpu.step().unwrap()).collect();
+        assert_eq!(cycles, vec![3, 2, 2, 2, 3, 2, 2, 3, 5, 4]);
+        assert_eq!((cpu.bus[0xC000], cpu.bus[0xC001], cpu.bus[0xFF80]), (0x99, 0x99, 0x99));
+        assert_eq!(cpu.fetch16(R16::HL), 0xC000);
+        assert_eq!((cpu.bus[0xD000], cpu.bus[0xD001]), (0x34, 0x12));
+        assert_eq!(cpu.fetch8(R8::A), 0x12);
+    }
+    // Checks that EI only lets an interrupt in after the instruction following it
+    #[test]
+    fn cpu_enables_interrupts_one_instruction_late() {
+        let mut mem = [0u8; 0x10000];
+        mem[..3].copy_from_slice(&[0xFB, 0x00, 0x00]); // EI ; NOP ; NOP
+        mem[0xFFFF] = 0x04;
+        mem[0xFF0F] = 0x04;
+        let mut cpu = CPU::new(mem);
+        cpu.sp = 0xD000;
+        cpu.step().unwrap();
+        assert!(!cpu.ime);
+        assert_eq!(cpu.step(), Ok(1));
+        assert_eq!(cpu.pc, 0x0002);
+        assert!(cpu.ime);
+        assert_eq!(cpu.step(), Ok(5));
+        assert_eq!(cpu.pc, 0x0050);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.bus[0xFF0F], 0x00);
+        assert_eq!(cpu.sp, 0xCFFE);
+        assert_eq!((cpu.bus[0xCFFF], cpu.bus[0xCFFE]), (0x00, 0x02));
+    }
+    // Checks that DI straight after EI keeps interrupts disabled
+    #[test]
+    fn cpu_di_cancels_pending_ei() {
+        let mut mem = [0u8; 0x10000];
+        mem[..3].copy_from_slice(&[0xFB, 0xF3, 0x00]); // EI ; DI ; NOP
+        mem[0xFFFF] = 0x01;
+        mem[0xFF0F] = 0x01;
+        let mut cpu = CPU::new(mem);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert!(!cpu.ime);
+        assert_eq!(cpu.pc, 0x0003);
+    }
+    // Checks that interrupts are serviced in priority order, each RETI re-enabling IME at once
+    #[test]
+    fn cpu_services_interrupts_in_priority_order() {
+        let mut mem = [0u8; 0x10000];
+        for interrupt in Interrupt::all().iter() {
+            mem[interrupt.vector() as usize] = 0xD9; // RETI
+        }
+        mem[0xFFFF] = 0x1F;
+        mem[0xFF0F] = 0x1F;
+        let mut cpu = CPU::new(mem);
+        cpu.sp = 0xD000;
+        cpu.ime = true;
+        for interrupt in Interrupt::all().iter() {
+            assert_eq!(cpu.step(), Ok(5));
+            assert_eq!(cpu.pc, interrupt.vector());
+            cpu.step().unwrap();
+            assert!(cpu.ime);
+            assert_eq!(cpu.pc, 0x0000);
+        }
+        assert_eq!(cpu.bus[0xFF0F], 0x00);
+        assert_eq!(cpu.step(), Ok(1));
+    }
+    // Checks that an interrupt cancelled by pushing PC over IE jumps to 0x0000
+    #[test]
+    fn cpu_dispatch_can_be_cancelled_by_writing_ie() {
+        let mut mem = [0u8; 0x10000];
+        mem[0xFFFF] = 0x01;
+        mem[0xFF0F] = 0x01;
+        let mut cpu = CPU::new(mem);
+        cpu.pc = 0x0234;
+        cpu.sp = 0x0000;
+        cpu.ime = true;
+        assert_eq!(cpu.step(), Ok(5));
+        // The high byte of PC, 0x02, replaced IE and disabled VBlank
+        assert_eq!(cpu.bus[0xFFFF], 0x02);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.bus[0xFF0F], 0x01);
+    }
+    // Checks that HALT with IME set idles until an interrupt arrives, then services it
+    #[test]
+    fn cpu_halt_waits_for_interrupt() {
+        let mut mem = [0u8; 0x10000];
+        mem[0] = 0x76; // HALT
+        mem[0xFFFF] = 0x01;
+        let mut cpu = CPU::new(mem);
+        cpu.sp = 0xD000;
+        cpu.ime = true;
+        cpu.step().unwrap();
+        assert_eq!(cpu.mode(), Mode::Halted);
+        for _ in 0..10 {
+            assert_eq!(cpu.step(), Ok(1));
+            assert_eq!(cpu.pc, 0x0001);
+        }
+        cpu.bus[0xFF0F] = 0x01;
+        assert_eq!(cpu.step(), Ok(1));
+        assert_eq!(cpu.mode(), Mode::Running);
+        assert_eq!(cpu.step(), Ok(5));
+        assert_eq!(cpu.pc, 0x0040);
+        assert_eq!((cpu.bus[0xCFFF], cpu.bus[0xCFFE]), (0x00, 0x01));
+    }
+    // Checks that HALT with IME clear wakes up on an interrupt without servicing it
+    #[test]
+    fn cpu_halt_wakes_without_ime() {
+        let mut mem = [0u8; 0x10000];
+        mem[..2].copy_from_slice(&[0x76, 0x3C]); // HALT ; INC A
+        mem[0xFFFF] = 0x04;
+        let mut cpu = CPU::new(mem);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.mode(), Mode::Halted);
+        cpu.bus[0xFF0F] = 0x04;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x0002);
+        assert_eq!(cpu.fetch8(R8::A), 1);
+        assert_eq!(cpu.bus[0xFF0F], 0x04);
+    }
+    // Checks that HALT with IME clear and an interrupt pending reads the next byte twice
+    #[test]
+    fn cpu_halt_bug_repeats_next_byte() {
+        let mut mem = [0u8; 0x10000];
+        mem[..3].copy_from_slice(&[0x76, 0x3C, 0x00]); // HALT ; INC A ; NOP
+        mem[0xFFFF] = 0x01;
+        mem[0xFF0F] = 0x01;
+        let mut cpu = CPU::new(mem);
+        cpu.step().unwrap();
+        assert_eq!(cpu.mode(), Mode::Running);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.fetch8(R8::A), 2);
+        assert_eq!(cpu.pc, 0x0003);
+        // An operand byte is affected just the same: LD A,n reads its own opcode as n
+        mem[..3].copy_from_slice(&[0x76, 0x3E, 0x14]); // HALT ; LD A,$14
+        let mut cpu = CPU::new(mem);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.fetch8(R8::A), 0x3E);
+        assert_eq!(cpu.pc, 0x0002);
+    }
+    // Checks that an interrupt pending during EI ; HALT returns to the HALT
+    #[test]
+    fn cpu_ei_halt_returns_to_halt() {
+        let mut mem = [0u8; 0x10000];
+        mem[..2].copy_from_slice(&[0xFB, 0x76]); // EI ; HALT
+        mem[0x40] = 0xD9; // RETI
+        mem[0xFFFF] = 0x01;
+        mem[0xFF0F] = 0x01;
+        let mut cpu = CPU::new(mem);
+        cpu.sp = 0xD000;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.mode(), Mode::Running);
+        assert_eq!(cpu.step(), Ok(5));
+        assert_eq!((cpu.bus[0xCFFF], cpu.bus[0xCFFE]), (0x00, 0x01));
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x0001);
+        cpu.step().unwrap();
+        assert_eq!(cpu.mode(), Mode::Halted);
+    }
+    // Checks that STOP resets DIV and sleeps until a joypad line goes low
+    #[test]
+    fn cpu_stop_waits_for_joypad() {
+        let mut mem = [0u8; 0x10000];
+        mem[..3].copy_from_slice(&[0x10, 0x00, 0x3C]); // STOP ; INC A
+        mem[0xFF00] = 0xFF;
+        mem[0xFF04] = 0xAB;
+        mem[0xFFFF] = 0x1F;
+        mem[0xFF0F] = 0x1F;
+        let mut cpu = CPU::new(mem);
+        // The padding byte is fetched along with the opcode
+        assert_eq!(cpu.step(), Ok(2));
+        assert_eq!((cpu.mode(), cpu.pc), (Mode::Stopped, 0x0002));
+        assert_eq!(cpu.bus[0xFF04], 0x00);
+        for _ in 0..10 {
+            assert_eq!(cpu.step(), Ok(1));
+            assert_eq!(cpu.mode(), Mode::Stopped);
+        }
+        cpu.bus[0xFF00] = 0xEE;
+        cpu.step().unwrap();
+        assert_eq!(cpu.mode(), Mode::Running);
+        cpu.step().unwrap();
+        assert_eq!(cpu.fetch8(R8::A), 1);
+    }
+    // Checks ADC, SUB, SBC and CP against every pair of operands and carry in
+    #[test]
+    fn cpu_can_add_and_subtract_with_carry() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for i in 0..=u8::MAX {
+            for j in 0..=u8::MAX {
+                for &carry in &[false, true] {
+                    let c = carry as i16;
+                    let (wi, wj) = (i as i16, j as i16);
+                    // ADC
+                    cpu.set8(R8::A, i);
+                    cpu.set8(R8::B, j);
+                    cpu.flags.carry = carry;
+                    cpu.adc(R8::B);
+                    let res = wi + wj + c;
+                    assert_eq!(cpu.fetch8(R8::A), (res % 256) as u8);
+                    assert_eq!(cpu.flags.zero, res % 256 == 0);
+                    assert!(!cpu.flags.subtract);
+                    assert_eq!(cpu.flags.half_carry, wi % 16 + wj % 16 + c > 15);
+                    assert_eq!(cpu.flags.carry, res > 255);
+                    // SBC
+                    cpu.set8(R8::A, i);
+                    cpu.flags.carry = carry;
+                    cpu.sbc(R8::B);
+                    let res = wi - wj - c;
+                    assert_eq!(cpu.fetch8(R8::A), ((res + 256) % 256) as u8);
+                    assert_eq!(cpu.flags.zero, res == 0 || res == -256);
+                    assert!(cpu.flags.subtract);
+                    assert_eq!(cpu.flags.half_carry, wi % 16 - wj % 16 - c < 0);
+                    assert_eq!(cpu.flags.carry, res < 0);
+                    // SUB and CP ignore the carry in, and CP leaves A alone
+                    cpu.set8(R8::A, i);
+                    cpu.flags.carry = carry;
+                    cpu.cp(R8::B);
+                    let cp_flags = cpu.flags.to_byte();
+                    assert_eq!(cpu.fetch8(R8::A), i);
+                    cpu.flags.carry = carry;
+                    cpu.sub(R8::B);
+                    assert_eq!(cpu.flags.to_byte(), cp_flags);
+                    let res = wi - wj;
+                    assert_eq!(cpu.fetch8(R8::A), ((res + 256) % 256) as u8);
+                    assert_eq!(cpu.flags.zero, res == 0);
+                    assert!(cpu.flags.subtract);
+                    assert_eq!(cpu.flags.half_carry, wi % 16 < wj % 16);
+                    assert_eq!(cpu.flags.carry, res < 0);
+                }
+            }
+        }
+    }
+    // Checks AND, XOR and OR against every pair of operands
+    #[test]
+    fn cpu_can_and_xor_or() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for i in 0..=u8::MAX {
+            for j in 0..=u8::MAX {
+                let ops = [(Alu::And, i & j, 0x20), (Alu::Xor, i ^ j, 0), (Alu::Or, i | j, 0)];
+                for &(op, res, f) in &ops {
+                    cpu.set8(R8::A, i);
+                    cpu.flags = Flags::from_byte(0xF0);
+                    cpu.alu(op, Operand::Reg(R8::CONST(j)));
+                    assert_eq!(cpu.fetch8(R8::A), res);
+                    let zero = if res == 0 { 0x80 } else { 0x00 };
+                    assert_eq!(cpu.flags.to_byte(), zero | f);
+                }
+            }
+        }
+    }
+    // Checks INC and DEC on registers and (HL), which must leave the carry flag untouched
+    #[test]
+    fn cpu_can_inc_and_dec() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        cpu.set16(R16::HL, 0xC000);
+        for i in 0..=u8::MAX {
+            for &carry in &[false, true] {
+                for &operand in &[Operand::Reg(R8::D), Operand::Ind(R16::HL)] {
+                    cpu.write8(operand, i);
+                    cpu.flags.carry = carry;
+                    cpu.inc(operand);
+                    let res = cpu.read8(operand);
+                    assert_eq!(res, ((i as u16 + 1) % 256) as u8);
+                    assert_eq!(cpu.flags.zero, res == 0);
+                    assert!(!cpu.flags.subtract);
+                    assert_eq!(cpu.flags.half_carry, i % 16 == 15);
+                    assert_eq!(cpu.flags.carry, carry);
+                    cpu.write8(operand, i);
+                    cpu.dec(operand);
+                    let res = cpu.read8(operand);
+                    assert_eq!(res, ((i as u16 + 255) % 256) as u8);
+                    assert_eq!(cpu.flags.zero, res == 0);
+                    assert!(cpu.flags.subtract);
+                    assert_eq!(cpu.flags.half_carry, i % 16 == 0);
+                    assert_eq!(cpu.flags.carry, carry);
+                }
+            }
+        }
+    }
+    // Checks that CPL flips A and sets N and H, leaving Z and C alone
+    #[test]
+    fn cpu_can_complement_a() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for i in 0..=u8::MAX {
+            for f in 0..16u8 {
+                cpu.set8(R8::A, i);
+                cpu.flags = Flags::from_byte(f << 4);
+                cpu.cpl();
+                assert_eq!(cpu.fetch8(R8::A), 255 - i);
+                assert_eq!(cpu.flags.to_byte(), (f << 4) | 0x60);
+            }
+        }
+    }
+    // Checks that DAA turns binary results back into BCD for every pair of BCD operands,
+    // after ADD, ADC, SUB and SBC alike
+    #[test]
+    fn cpu_daa_corrects_bcd_arithmetic() {
+        let bcd = |n: u16| (((n / 10) << 4) | (n % 10)) as u8;
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for a in 0..100u16 {
+            for b in 0..100u16 {
+                for &carry in &[false, true] {
+                    let c = carry as u16;
+                    cpu.set8(R8::A, bcd(a));
+                    cpu.flags.carry = carry;
+                    cpu.adc(R8::CONST(bcd(b)));
+                    cpu.daa();
+                    let (sum, overflow) = ((a + b + c) % 100, a + b + c >= 100);
+                    assert_eq!(cpu.fetch8(R8::A), bcd(sum), "{} + {} + {}", a, b, c);
+                    assert_eq!(cpu.flags.carry, overflow);
+                    assert_eq!(cpu.flags.zero, sum == 0);
+                    assert!(!cpu.flags.half_carry);
+                    cpu.set8(R8::A, bcd(a));
+                    cpu.flags.carry = carry;
+                    cpu.sbc(R8::CONST(bcd(b)));
+                    cpu.daa();
+                    let difference = (a + 200 - b - c) % 100;
+                    assert_eq!(cpu.fetch8(R8::A), bcd(difference), "{} - {} - {}", a, b, c);
+                    assert_eq!(cpu.flags.carry, a < b + c);
+                    assert_eq!(cpu.flags.zero, difference == 0);
+                    assert!(cpu.flags.subtract);
+                }
+            }
+        }
+    }
+    // Checks ADD HL,rr across a spread of operands, including every pair register
+    #[test]
+    fn cpu_can_add_16_bit_registers() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for i in (0..=u16::MAX).step_by(0x0111) {
+            for j in (0..=u16::MAX).step_by(0x0107) {
+                for &zero in &[false, true] {
+                    cpu.set16(R16::HL, i);
+                    cpu.set16(R16::DE, j);
+                    cpu.flags = Flags::from_byte(if zero { 0xF0 } else { 0x70 });
+                    let start = cpu.cycles();
+                    cpu.add_hl(R16::DE);
+                    // ADD HL,rr should take 2 m-cycles, 1 of them the opcode fetch
+                    assert_eq!(cpu.cycles() - start, 4);
+                    let res = i as u32 + j as u32;
+                    assert_eq!(cpu.fetch16(R16::HL), res as u16);
+                    assert_eq!(cpu.flags.zero, zero);
+                    assert!(!cpu.flags.subtract);
+                    assert_eq!(cpu.flags.half_carry, (i % 0x1000) + (j % 0x1000) >= 0x1000);
+                    assert_eq!(cpu.flags.carry, res > 0xFFFF);
+                }
+            }
+        }
+        for reg in &[R16::BC, R16::DE, R16::SP] {
+            cpu.set16(R16::HL, 0x8A23);
+            cpu.set16(*reg, 0x0605);
+            cpu.add_hl(*reg);
+            assert_eq!(cpu.fetch16(R16::HL), 0x9028);
+            assert_eq!(cpu.flags.to_byte() & 0x70, 0x20);
+        }
+        cpu.set16(R16::HL, 0x8A23);
+        cpu.add_hl(R16::HL);
+        assert_eq!(cpu.fetch16(R16::HL), 0x1446);
+        assert_eq!(cpu.flags.to_byte() & 0x70, 0x30);
+    }
+    // Checks ADD SP,e and LD HL,SP+e for every offset against every low byte of SP
+    #[test]
+    fn cpu_can_offset_sp() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for &high in &[0x00u16, 0x7F, 0xFF] {
+            for low in 0..=0xFFu16 {
+                for e in 0..=u8::MAX {
+                    let sp = (high << 8) | low;
+                    let expected = (sp as i32 + e as i8 as i32) as u16;
+                    let half_carry = (low % 16) + (e as u16 % 16) > 15;
+                    let carry = low + e as u16 > 255;
+                    let flags = ((half_carry as u8) << 5) | ((carry as u8) << 4);
+                    cpu.sp = sp;
+                    cpu.flags = Flags::from_byte(0xC0);
+                    let start = cpu.cycles();
+                    cpu.load_hl_sp(e as i8);
+                    // LD HL,SP+e should take 3 m-cycles, 2 of them fetching the opcode and e
+                    assert_eq!(cpu.cycles() - start, 4);
+                    assert_eq!((cpu.fetch16(R16::HL), cpu.sp), (expected, sp));
+                    assert_eq!(cpu.flags.to_byte(), flags);
+                    cpu.flags = Flags::from_byte(0xC0);
+                    let start = cpu.cycles();
+                    cpu.add_sp(e as i8);
+                    // ADD SP,e should take 4 m-cycles, 2 of them fetching the opcode and e
+                    assert_eq!(cpu.cycles() - start, 8);
+                    assert_eq!(cpu.sp, expected);
+                    assert_eq!(cpu.flags.to_byte(), flags);
+                }
+            }
+        }
+    }
+    // Checks that INC rr and DEC rr wrap around, take 2 m-cycles and leave the flags alone
+    #[test]
+    fn cpu_can_inc_and_dec_16_bit_registers() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        for reg in &[R16::BC, R16::DE, R16::HL, R16::SP] {
+            for i in 0..=u16::MAX {
+                cpu.set16(*reg, i);
+                cpu.flags = Flags::from_byte(0xA0);
+                let start = cpu.cycles();
+                cpu.inc16(*reg);
+                // INC rr should take 2 m-cycles, 1 of them the opcode fetch
+                assert_eq!(cpu.cycles() - start, 4);
+                assert_eq!(cpu.fetch16(*reg), i.wrapping_add(1));
+                cpu.dec16(*reg);
+                cpu.dec16(*reg);
+                // DEC rr should take 2 m-cycles, 1 of them the opcode fetch
+                assert_eq!(cpu.cycles() - start, 12);
+                assert_eq!(cpu.fetch16(*reg), i.wrapping_sub(1));
+                assert_eq!(cpu.flags.to_byte(), 0xA0);
+            }
+        }
+    }
+    // Checks every conditional JR, JP, CALL and RET against every combination of Z and C
+    #[test]
+    fn cpu_conditional_branches_only_pay_when_taken() {
+        // Opcode of the NZ variant, length, cycles taken and cycles not taken
+        let branches = [(0x20, 2, 3, 2), (0xC2, 3, 4, 3), (0xC4, 3, 6, 3), (0xC0, 1, 5, 2)];
+        for &(base, length, taken, not_taken) in &branches {
+            for cond in 0..4u8 {
+                for f in &[0x00u8, 0x10, 0x80, 0x90] {
+                    let opcode = base | (cond << 3);
+                    let mut mem = [0u8; 0x10000];
+                    mem[0x100..0x103].copy_from_slice(&[opcode, 0x00, 0x20]);
+                    mem[0xCFFE..0xD000].copy_from_slice(&[0x34, 0x12]);
+                    let mut cpu = CPU::new(mem);
+                    cpu.pc = 0x100;
+                    cpu.sp = 0xCFFE;
+                    cpu.set16(R16::AF, *f as u16);
+                    let expected = [
+                        f & 0x80 == 0,
+                        f & 0x80 != 0,
+                        f & 0x10 == 0,
+                        f & 0x10 != 0,
+                    ][cond as usize];
+                    let conditions = [Condition::NZ, Condition::Z, Condition::NC, Condition::C];
+                    assert_eq!(cpu.check(conditions[cond as usize]), expected);
+                    let cycles = cpu.step().unwrap();
+                    if expected {
+                        assert_eq!(cycles, taken, "{:#04X} with F={:02X}", opcode, f);
+                        let target = match base {
+                            0x20 => 0x0102,
+                            0xC0 => 0x1234,
+                            _ => 0x2000,
+                        };
+                        assert_eq!(cpu.pc, target);
+                    } else {
+                        assert_eq!(cycles, not_taken, "{:#04X} with F={:02X}", opcode, f);
+                        assert_eq!(cpu.pc, 0x100 + length);
+                        assert_eq!(cpu.sp, 0xCFFE);
+                    }
+                }
+            }
+        }
+    }
+    // Checks the unconditional forms, and that RET costs less than a taken RET cc
+    #[test]
+    fn cpu_can_jump_call_and_return() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        cpu.sp = 0xD000;
+        cpu.pc = 0x0150;
+        // Each helper is timed without the opcode and operand fetches that precede it
+        let mut start = cpu.cycles();
+        let mut elapsed = |cpu: &CPU<[u8; 0x10000]>| {
+            let m = (cpu.cycles() - start) / 4;
+            start = cpu.cycles();
+            m
+        };
+        assert!(cpu.call(Condition::Always, 0x4000));
+        assert_eq!(elapsed(&cpu), 3); // CALL nn should take 6 m-cycles
+        assert_eq!((cpu.pc, cpu.sp), (0x4000, 0xCFFE));
+        cpu.rst(0x38);
+        assert_eq!(elapsed(&cpu), 3); // RST n should take 4 m-cycles
+        assert_eq!((cpu.pc, cpu.sp), (0x0038, 0xCFFC));
+        assert!(cpu.ret(Condition::Always));
+        assert_eq!(elapsed(&cpu), 3); // RET should take 4 m-cycles
+        assert_eq!(cpu.pc, 0x4000);
+        assert!(cpu.jump_relative(Condition::Always, -0x10));
+        assert_eq!(elapsed(&cpu), 1); // JR e should take 3 m-cycles
+        assert_eq!(cpu.pc, 0x3FF0);
+        assert!(cpu.jump(Condition::Always, 0x1234));
+        assert_eq!(elapsed(&cpu), 1); // JP nn should take 4 m-cycles
+        cpu.reti();
+        assert_eq!(elapsed(&cpu), 3); // RETI should take 4 m-cycles
+        assert_eq!((cpu.pc, cpu.sp), (0x0150, 0xD000));
+        assert!(cpu.ime);
+    }
+    // Checks that every register pair survives a trip through the stack, F losing its low nibble
+    #[test]
+    fn cpu_can_push_and_pop_every_pair() {
+        let mut cpu = CPU::new([0u8; 0x10000]);
+        cpu.sp = 0xFFFE;
+        let pairs = [R16::AF, R16::BC, R16::DE, R16::HL];
+        for i in (0..=u16::MAX).step_by(0x0F0F) {
+            for from in pairs.iter() {
+                for to in pairs.iter() {
+                    cpu.set16(*to, 0x0000);
+                    cpu.set16(*from, i);
+                    let start = cpu.cycles();
+                    cpu.push(*from);
+                    // PUSH rr should take 4 m-cycles, 1 of them the opcode fetch
+                    assert_eq!(cpu.cycles() - start, 12);
+                    assert_eq!(cpu.sp, 0xFFFC);
+                    cpu.pop(*to);
+                    // POP rr should take 3 m-cycles, 1 of them the opcode fetch
+                    assert_eq!(cpu.cycles() - start, 20);
+                    assert_eq!(cpu.sp, 0xFFFE);
+                    let expected = if *from == R16::AF || *to == R16::AF {
+                        i & 0xFFF0
+                    } else {
+                        i
+                    };
+                    assert_eq!(cpu.fetch16(*to), expected);
+                }
+            }
+        }
+    }
+    // Checks that each RST opcode calls its own vector
+    #[test]
+    fn cpu_can_restart_to_every_vector() {
+        for vector in (0x00..=0x38u8).step_by(8) {
+            let mut mem = [0u8; 0x10000];
+            mem[0x1234] = 0xC7 | vector;
+            let mut cpu = CPU::new(mem);
+            cpu.pc = 0x1234;
+            cpu.sp = 0xD000;
+            assert_eq!(cpu.step(), Ok(4));
+            assert_eq!(cpu.pc, vector as u16);
+            assert_eq!((cpu.bus[0xCFFF], cpu.bus[0xCFFE]), (0x12, 0x35));
+        }
+    }
+    // Checks that every illegal opcode locks the CPU up for good, even with interrupts pending
+    #[test]
+    fn cpu_locks_up_on_illegal_opcodes() {
+        let illegal = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+        for &opcode in illegal.iter() {
+            let mut mem = [0u8; 0x10000];
+            mem[0x100] = opcode;
+            mem[0xFFFF] = 0x1F;
+            let mut cpu = CPU::new(mem);
+            cpu.pc = 0x100;
+            assert_eq!(cpu.step(), Ok(1));
+            assert_eq!(cpu.mode(), Mode::Locked);
+            cpu.ime = true;
+            cpu.bus[0xFF0F] = 0x1F;
+            for _ in 0..10 {
+                assert_eq!(cpu.step(), Ok(1));
+            }
+            assert_eq!(cpu.pc, 0x101);
+            assert_eq!(cpu.mode(), Mode::Locked);
+        }
+    }
+    // Checks that a reported illegal opcode comes back from the step that ran into it, with its
+    // bank and address, and only from that step
+    #[test]
+    fn cpu_reports_illegal_opcodes() {
+        let mut mem = [0u8; 0x10000];
+        mem[0x4A2C] = 0xDD;
+        let mut cpu = CPU::new(mem);
+        cpu.set_illegal_policy(IllegalPolicy::Report);
+        cpu.pc = 0x4A2C;
+        let illegal = cpu.step().unwrap_err();
+        assert_eq!(illegal.to_string(), "Illegal opcode $DD at 00:4A2C");
+        assert_eq!((cpu.mode(), cpu.cycles()), (Mode::Locked, 4));
+        assert_eq!(cpu.step(), Ok(1));
+        // The same goes for the block cache
+        let mut cpu = CPU::new(mem);
+        cpu.set_illegal_policy(IllegalPolicy::Report);
+        cpu.set_block_cache(true);
+        cpu.pc = 0x4A2C;
+        assert_eq!(cpu.step(), Err(illegal));
+        assert_eq!(cpu.step(), Ok(1));
+    }
 }
@@ -0,0 +1,403 @@
+use std::fmt;
+
+/// The logo every cartridge carries at 0x0104-0x0133, which the boot ROM checks byte for byte
+pub static NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// Address just past the end of the header, so the smallest ROM that can hold one
+const HEADER_END: usize = 0x0150;
+
+/// Everything the cartridge header at 0x0100-0x014F says about the cartridge
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The game's title in upper case ASCII, without the padding
+    pub title: String,
+    /// The four character manufacturer code some later cartridges carry after the title
+    pub manufacturer: Option<String>,
+    pub cgb: CgbSupport,
+    /// Whether the game uses the Super Game Boy's functions
+    pub sgb: bool,
+    pub licensee: Licensee,
+    pub cartridge_type: CartridgeType,
+    /// ROM size in bytes
+    pub rom_size: usize,
+    /// External RAM size in bytes, not counting RAM built into the mapper like the MBC2's
+    pub ram_size: usize,
+    /// The mask ROM version, usually 0
+    pub version: u8,
+    /// Checksum of 0x0134-0x014C, which the boot ROM refuses to boot without
+    pub header_checksum: u8,
+    /// Sum of every other byte of the ROM, which nothing on the hardware checks
+    pub global_checksum: u16,
+}
+
+/// How a cartridge treats the Game Boy Color, from the flag at 0x0143
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    /// Made before the CGB, runs in DMG compatibility mode
+    None,
+    /// Uses CGB functions but also runs on older models
+    Enhanced,
+    /// Only runs on the CGB
+    Only,
+}
+
+/// Who published the game
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Licensee {
+    /// A code from the old licensee byte at 0x014B
+    Old(u8),
+    /// A two character code from 0x0144-0x0145, used when the old byte is 0x33
+    New(String),
+}
+
+/// The mapper on a cartridge, which decides how its ROM and RAM are banked in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+}
+
+/// The hardware on a cartridge, from the type byte at 0x0147
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    /// Whether there is external RAM
+    pub ram: bool,
+    /// Whether a battery keeps RAM or the clock going while the power is off
+    pub battery: bool,
+    /// Whether there is a real time clock
+    pub timer: bool,
+    /// Whether there is a rumble motor
+    pub rumble: bool,
+}
+
+/// Why a ROM image has no usable header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The image ends before the header does. Holds the image size.
+    Truncated(usize),
+    /// The logo at 0x0104 is not the Nintendo logo
+    BadLogo,
+    /// The header checksum at 0x014D does not match the header
+    HeaderChecksum { expected: u8, actual: u8 },
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    /// The image is not as big as the header says the ROM is
+    RomSize { header: usize, actual: usize },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderError::Truncated(size) => write!(
+                f,
+                "ROM is {} bytes, too short to hold a header ending at {:#06X}",
+                size, HEADER_END
+            ),
+            HeaderError::BadLogo => write!(f, "ROM does not carry the Nintendo logo"),
+            HeaderError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "Header checksum is {:#04X} but the header sums to {:#04X}",
+                expected, actual
+            ),
+            HeaderError::UnknownCartridgeType(code) => {
+                write!(f, "Unknown cartridge type {:#04X}", code)
+            }
+            HeaderError::UnknownRomSize(code) => write!(f, "Unknown ROM size code {:#04X}", code),
+            HeaderError::UnknownRamSize(code) => write!(f, "Unknown RAM size code {:#04X}", code),
+            HeaderError::RomSize { header, actual } => write!(
+                f,
+                "Header says the ROM is {} bytes, but it is {} bytes",
+                header, actual
+            ),
+        }
+    }
+}
+
+impl CartridgeType {
+    /// USAGE: CartridgeType::from_code(CODE) where CODE is the type byte at 0x0147
+    /// Returns the hardware CODE stands for, or None if it is not a known type
+    pub fn from_code(code: u8) -> Option<CartridgeType> {
+        let (mapper, ram, battery, timer, rumble) = match code {
+            0x00 => (Mapper::RomOnly, false, false, false, false),
+            0x01 => (Mapper::Mbc1, false, false, false, false),
+            0x02 => (Mapper::Mbc1, true, false, false, false),
+            0x03 => (Mapper::Mbc1, true, true, false, false),
+            0x05 => (Mapper::Mbc2, false, false, false, false),
+            0x06 => (Mapper::Mbc2, false, true, false, false),
+            0x08 => (Mapper::RomOnly, true, false, false, false),
+            0x09 => (Mapper::RomOnly, true, true, false, false),
+            0x0B => (Mapper::Mmm01, false, false, false, false),
+            0x0C => (Mapper::Mmm01, true, false, false, false),
+            0x0D => (Mapper::Mmm01, true, true, false, false),
+            0x0F => (Mapper::Mbc3, false, true, true, false),
+            0x10 => (Mapper::Mbc3, true, true, true, false),
+            0x11 => (Mapper::Mbc3, false, false, false, false),
+            0x12 => (Mapper::Mbc3, true, false, false, false),
+            0x13 => (Mapper::Mbc3, true, true, false, false),
+            0x19 => (Mapper::Mbc5, false, false, false, false),
+            0x1A => (Mapper::Mbc5, true, false, false, false),
+            0x1B => (Mapper::Mbc5, true, true, false, false),
+            0x1C => (Mapper::Mbc5, false, false, false, true),
+            0x1D => (Mapper::Mbc5, true, false, false, true),
+            0x1E => (Mapper::Mbc5, true, true, false, true),
+            0x20 => (Mapper::Mbc6, true, true, false, false),
+            0x22 => (Mapper::Mbc7, true, true, false, true),
+            0xFC => (Mapper::PocketCamera, true, true, false, false),
+            0xFD => (Mapper::Tama5, true, true, true, false),
+            0xFE => (Mapper::HuC3, true, true, true, false),
+            0xFF => (Mapper::HuC1, true, true, false, false),
+            _ => return None,
+        };
+        Some(CartridgeType {
+            code,
+            mapper,
+            ram,
+            battery,
+            timer,
+            rumble,
+        })
+    }
+}
+
+impl Header {
+    /// USAGE: Header::parse(ROM) where ROM is a whole ROM image
+    /// Returns the header of ROM, after checking everything the boot ROM checks, that every
+    /// field is known and that ROM is as big as the header says
+    pub fn parse(rom: &[u8]) -> Result<Header, HeaderError> {
+        if rom.len() < HEADER_END {
+            return Err(HeaderError::Truncated(rom.len()));
+        }
+        if rom[0x0104..0x0134] != NINTENDO_LOGO[..] {
+            return Err(HeaderError::BadLogo);
+        }
+        let actual = Header::checksum_of(rom);
+        if rom[0x014D] != actual {
+            return Err(HeaderError::HeaderChecksum {
+                expected: rom[0x014D],
+                actual,
+            });
+        }
+        let cartridge_type = CartridgeType::from_code(rom[0x0147])
+            .ok_or(HeaderError::UnknownCartridgeType(rom[0x0147]))?;
+        let rom_size = match rom[0x0148] {
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(HeaderError::UnknownRomSize(code)),
+        };
+        let ram_size = match rom[0x0149] {
+            0x00 => 0,
+            // Listed in some docs, but no known cartridge has it
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(HeaderError::UnknownRamSize(code)),
+        };
+        if rom.len() != rom_size {
+            return Err(HeaderError::RomSize {
+                header: rom_size,
+                actual: rom.len(),
+            });
+        }
+        let cgb = match rom[0x0143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+        // Cartridges made with the CGB in mind may end the title early with a manufacturer
+        // code, which is always four upper case letters or digits
+        let code = &rom[0x013F..0x0143];
+        let has_manufacturer = cgb != CgbSupport::None
+            && code
+                .iter()
+                .all(|&c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        let (title, manufacturer) = match (cgb, has_manufacturer) {
+            (_, true) => (&rom[0x0134..0x013F], Some(ascii(code))),
+            (CgbSupport::None, _) => (&rom[0x0134..0x0144], None),
+            _ => (&rom[0x0134..0x0143], None),
+        };
+        let licensee = match rom[0x014B] {
+            0x33 => Licensee::New(ascii(&rom[0x0144..0x0146])),
+            code => Licensee::Old(code),
+        };
+        Ok(Header {
+            title: ascii(title),
+            manufacturer,
+            cgb,
+            sgb: rom[0x0146] == 0x03,
+            licensee,
+            cartridge_type,
+            rom_size,
+            ram_size,
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            global_checksum: (rom[0x014E] as u16) << 8 | rom[0x014F] as u16,
+        })
+    }
+    /// USAGE: Header::checksum_of(ROM) where ROM holds at least the header
+    /// Returns the header checksum of ROM, as the boot ROM works it out
+    pub fn checksum_of(rom: &[u8]) -> u8 {
+        rom[0x0134..0x014D]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1))
+    }
+    /// USAGE: Header::global_checksum_of(ROM)
+    /// Returns the sum of every byte of ROM except the global checksum itself
+    pub fn global_checksum_of(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|&(address, _)| address != 0x014E && address != 0x014F)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+    }
+}
+
+/// Returns the printable ASCII at the start of BYTES, up to the first NUL
+/// Anything unprintable shows up as '?'
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| match byte {
+            0x20..=0x7E => byte as char,
+            _ => '?',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Returns a ROM-only image of SIZE bytes with a valid header
+    fn rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0; size];
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x014D] = Header::checksum_of(&rom);
+        rom
+    }
+    /// Sets the header byte at ADDRESS and fixes the checksum back up
+    fn patch(rom: &mut [u8], address: usize, value: u8) {
+        rom[address] = value;
+        rom[0x014D] = Header::checksum_of(rom);
+    }
+
+    // Checks that Tetris is a 32 KiB ROM-only cartridge with good checksums
+    #[test]
+    fn can_parse_tetris_header() {
+        let rom = include_bytes!("../tetris.gb");
+        let header = Header::parse(rom).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.manufacturer, None);
+        assert_eq!((header.cgb, header.sgb), (CgbSupport::None, false));
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+        assert_eq!(header.cartridge_type, CartridgeType::from_code(0x00).unwrap());
+        assert_eq!(header.cartridge_type.mapper, Mapper::RomOnly);
+        assert_eq!((header.rom_size, header.ram_size), (0x8000, 0));
+        assert_eq!(header.version, 0x00);
+        assert_eq!((header.header_checksum, header.global_checksum), (0x0B, 0x89B5));
+        assert_eq!(Header::global_checksum_of(rom), header.global_checksum);
+    }
+    // Checks that the boot ROM and the header agree on the logo
+    #[test]
+    fn boot_rom_carries_the_same_logo() {
+        let boot = include_bytes!("../DMG_ROM.bin");
+        assert_eq!(boot[0xA8..0xD8], NINTENDO_LOGO[..]);
+    }
+    // Checks each way a ROM can fail to have a usable header
+    #[test]
+    fn cannot_parse_malformed_headers() {
+        assert_eq!(Header::parse(&[]), Err(HeaderError::Truncated(0)));
+        assert_eq!(Header::parse(&rom(0x8000)[..0x014F]), Err(HeaderError::Truncated(0x014F)));
+        let mut bad = rom(0x8000);
+        bad[0x0110] ^= 0xFF;
+        assert_eq!(Header::parse(&bad), Err(HeaderError::BadLogo));
+        let mut bad = rom(0x8000);
+        bad[0x0134] = b'A';
+        let checksum = Header::parse(&bad);
+        let error = HeaderError::HeaderChecksum {
+            expected: 0xE7,
+            actual: 0xA6,
+        };
+        assert_eq!(checksum, Err(error.clone()));
+        assert_eq!(
+            error.to_string(),
+            "Header checksum is 0xE7 but the header sums to 0xA6"
+        );
+        let mut bad = rom(0x8000);
+        patch(&mut bad, 0x0147, 0x04);
+        assert_eq!(Header::parse(&bad), Err(HeaderError::UnknownCartridgeType(0x04)));
+        let mut bad = rom(0x8000);
+        patch(&mut bad, 0x0148, 0x09);
+        assert_eq!(Header::parse(&bad), Err(HeaderError::UnknownRomSize(0x09)));
+        let mut bad = rom(0x8000);
+        patch(&mut bad, 0x0149, 0x06);
+        assert_eq!(Header::parse(&bad), Err(HeaderError::UnknownRamSize(0x06)));
+        // A 64 KiB header on a 32 KiB image is a bad dump
+        let mut bad = rom(0x8000);
+        patch(&mut bad, 0x0148, 0x01);
+        let error = HeaderError::RomSize {
+            header: 0x10000,
+            actual: 0x8000,
+        };
+        assert_eq!(Header::parse(&bad), Err(error));
+    }
+    // Checks the CGB flag, manufacturer code, SGB flag and new licensee codes
+    #[test]
+    fn can_parse_later_header_fields() {
+        let mut rom = rom(0x8000);
+        rom[0x0134..0x0143].copy_from_slice(b"POKEMON_SLVAAXE");
+        patch(&mut rom, 0x0143, 0x80);
+        patch(&mut rom, 0x0146, 0x03);
+        patch(&mut rom, 0x014B, 0x33);
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        patch(&mut rom, 0x0147, 0x10);
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer, Some(String::from("AAXE")));
+        assert_eq!((header.cgb, header.sgb), (CgbSupport::Enhanced, true));
+        assert_eq!(header.licensee, Licensee::New(String::from("01")));
+        let cartridge_type = header.cartridge_type;
+        assert_eq!(cartridge_type.mapper, Mapper::Mbc3);
+        assert!(cartridge_type.ram && cartridge_type.battery && cartridge_type.timer);
+        // Without a manufacturer code the title runs up to the CGB flag
+        patch(&mut rom, 0x013F, b'?');
+        patch(&mut rom, 0x0143, 0xC0);
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!((header.title.as_str(), header.manufacturer), ("POKEMON_SLV?AXE", None));
+        assert_eq!(header.cgb, CgbSupport::Only);
+    }
+    // Checks that every listed type and size code is understood
+    #[test]
+    fn can_decode_every_known_code() {
+        let types = (0..=u8::MAX).filter_map(CartridgeType::from_code).count();
+        assert_eq!(types, 28);
+        for code in 0..=8 {
+            let mut rom = rom(0x8000 << code);
+            patch(&mut rom, 0x0148, code);
+            assert_eq!(Header::parse(&rom).unwrap().rom_size, 0x8000 << code);
+        }
+        let sizes = [0, 0x800, 0x2000, 0x8000, 0x20000, 0x10000];
+        for (code, &size) in sizes.iter().enumerate() {
+            let mut rom = rom(0x8000);
+            patch(&mut rom, 0x0149, code as u8);
+            assert_eq!(Header::parse(&rom).unwrap().ram_size, size);
+        }
+    }
+}
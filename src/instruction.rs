@@ -0,0 +1,118 @@
+use cpu::{Condition, Operand, R16};
+use opcode::Opcode;
+
+/// A single decoded SM83 instruction, with its operands
+/// Immediate operands are already read out of the instruction bytes. Lengths, timings and
+/// assembler syntax live in the opcode table in opcode.rs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    /// STOP, whose padding byte is fetched like an immediate and then ignored
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    /// LD TO,FROM for every 8-bit load, including memory and immediate operands
+    Ld(Operand, Operand),
+    /// LD rr,nn
+    Ld16(R16, u16),
+    /// LD (nn),SP
+    LdNnSp(u16),
+    /// LD SP,HL
+    LdSpHl,
+    /// LD HL,SP+e
+    LdHlSp(i8),
+    Push(R16),
+    Pop(R16),
+    /// ADD, ADC, SUB, SBC, AND, XOR, OR or CP between A and the operand
+    Alu(Alu, Operand),
+    Inc(Operand),
+    Dec(Operand),
+    Inc16(R16),
+    Dec16(R16),
+    AddHl(R16),
+    AddSp(i8),
+    /// RLCA, RRCA, RLA and RRA
+    RotateA(Shift),
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jp(Condition, u16),
+    /// JP HL
+    JpHl,
+    Jr(Condition, i8),
+    Call(Condition, u16),
+    Ret(Condition),
+    Reti,
+    Rst(u8),
+    /// One of the eight CB-prefixed shifts and rotates
+    Shift(Shift, Operand),
+    Bit(u8, Operand),
+    Res(u8, Operand),
+    Set(u8, Operand),
+    /// One of the 11 opcodes with no instruction behind them
+    Illegal(u8),
+}
+
+/// The eight accumulator operations, in opcode order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alu {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+/// The eight CB-prefixed shift operations, in opcode order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shift {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+impl Instruction {
+    /// USAGE: Instruction::decode(BYTES) where BYTES starts with an opcode
+    /// Returns the instruction at the start of BYTES, or None if BYTES is too short to hold it
+    /// Any bytes past the length of the instruction are ignored
+    pub fn decode(bytes: &[u8]) -> Option<Instruction> {
+        Opcode::lookup(bytes).map(|row| row.instruction(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::R8;
+
+    // Checks decoding through the CB prefix, and that cut off instructions do not decode
+    #[test]
+    fn can_decode_instructions() {
+        assert_eq!(Instruction::decode(&[]), None);
+        assert_eq!(Instruction::decode(&[0xCB]), None);
+        assert_eq!(Instruction::decode(&[0xC3, 0x50]), None);
+        assert_eq!(
+            Instruction::decode(&[0xC3, 0x50, 0x01, 0xFF]),
+            Some(Instruction::Jp(Condition::Always, 0x0150))
+        );
+        assert_eq!(
+            Instruction::decode(&[0xCB, 0x46]),
+            Some(Instruction::Bit(0, Operand::Ind(R16::HL)))
+        );
+        assert_eq!(
+            Instruction::decode(&[0xF0, 0x44]),
+            Some(Instruction::Ld(Operand::Reg(R8::A), Operand::High(0x44)))
+        );
+        assert_eq!(Instruction::decode(&[0xDD]), Some(Instruction::Illegal(0xDD)));
+    }
+}
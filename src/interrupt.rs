@@ -0,0 +1,88 @@
+use bus::Bus;
+
+/// Address of the interrupt enable register, IE
+pub const IE: u16 = 0xFFFF;
+/// Address of the interrupt flag register, IF
+pub const IF: u16 = 0xFF0F;
+
+/// The five interrupt sources, from highest to lowest priority
+/// Each source owns one bit of IE and IF, VBlank being bit 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// USAGE: Interrupt::all()
+    /// Returns every interrupt in priority order
+    pub fn all() -> [Interrupt; 5] {
+        [
+            Interrupt::VBlank,
+            Interrupt::Stat,
+            Interrupt::Timer,
+            Interrupt::Serial,
+            Interrupt::Joypad,
+        ]
+    }
+    /// USAGE: interrupt.bit()
+    /// Returns the mask of this interrupt's bit in IE and IF
+    pub fn bit(self) -> u8 {
+        1 << (self as u8)
+    }
+    /// USAGE: interrupt.vector()
+    /// Returns the address the CPU jumps to when servicing this interrupt
+    pub fn vector(self) -> u16 {
+        0x40 + 8 * (self as u16)
+    }
+    /// USAGE: Interrupt::highest(BITS) where BITS is a set of IE/IF bits
+    /// Returns the highest priority interrupt set in BITS, if any
+    pub fn highest(bits: u8) -> Option<Interrupt> {
+        Interrupt::all()
+            .iter()
+            .find(|interrupt| bits & interrupt.bit() != 0)
+            .cloned()
+    }
+    /// USAGE: interrupt.request(BUS)
+    /// Sets this interrupt's bit in IF, as the hardware raising it would
+    pub fn request<B: Bus>(self, bus: &mut B) {
+        let flags = bus.read(IF);
+        bus.write(IF, flags | self.bit());
+    }
+}
+
+/// USAGE: interrupt::pending(BUS)
+/// Returns the bits of interrupts that are both requested and enabled
+pub fn pending<B: Bus>(bus: &mut B) -> u8 {
+    bus.read(IE) & bus.read(IF) & 0x1F
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    // Checks that bits and vectors follow priority order
+    #[test]
+    fn interrupts_have_bits_and_vectors_in_priority_order() {
+        let vectors: Vec<(u8, u16)> = Interrupt::all()
+            .iter()
+            .map(|interrupt| (interrupt.bit(), interrupt.vector()))
+            .collect();
+        assert_eq!(
+            vectors,
+            vec![(0x01, 0x40), (0x02, 0x48), (0x04, 0x50), (0x08, 0x58), (0x10, 0x60)]
+        );
+    }
+    // Checks that the lowest set bit always wins, and that the unused bits are ignored
+    #[test]
+    fn highest_interrupt_is_lowest_bit() {
+        assert_eq!(Interrupt::highest(0x00), None);
+        assert_eq!(Interrupt::highest(0xE0), None);
+        for bits in 1..0x20u8 {
+            let expected = Interrupt::all()[bits.trailing_zeros() as usize];
+            assert_eq!(Interrupt::highest(bits), Some(expected));
+        }
+    }
+}
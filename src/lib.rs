@@ -0,0 +1,16 @@
+pub mod boot;
+pub mod bus;
+mod cache;
+pub mod cartridge;
+pub mod cpu;
+pub mod header;
+pub mod instruction;
+pub mod interrupt;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+pub mod mmu;
+pub mod model;
+pub mod opcode;
+pub mod rtc;
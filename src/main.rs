@@ -1,5 +1,57 @@
-mod cpu;
+extern crate gbrust;
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+
+use gbrust::header::{Header, Licensee};
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().collect();
+    if args.len() <= 1 {
+        fail("Not enough arguments");
+    }
+    let filename = &args[1];
+    let mut rom = Vec::new();
+    let read = File::open(filename).and_then(|mut file| file.read_to_end(&mut rom));
+    if read.is_err() {
+        fail(&format!("Not able to read input file {}", filename));
+    }
+    let header = match Header::parse(&rom) {
+        Ok(header) => header,
+        Err(error) => fail(&error.to_string()),
+    };
+    let kind = header.cartridge_type;
+    println!("Title:        {}", header.title);
+    if let Some(ref manufacturer) = header.manufacturer {
+        println!("Manufacturer: {}", manufacturer);
+    }
+    println!("CGB:          {:?}", header.cgb);
+    println!("SGB:          {}", header.sgb);
+    match header.licensee {
+        Licensee::Old(code) => println!("Licensee:     ${:02X}", code),
+        Licensee::New(ref code) => println!("Licensee:     {}", code),
+    }
+    println!("Type:         ${:02X} {:?}", kind.code, kind.mapper);
+    println!("RAM:          {}", kind.ram);
+    println!("Battery:      {}", kind.battery);
+    println!("Timer:        {}", kind.timer);
+    println!("Rumble:       {}", kind.rumble);
+    println!("ROM size:     {} KiB", header.rom_size / 1024);
+    println!("RAM size:     {} KiB", header.ram_size / 1024);
+    println!("Version:      {}", header.version);
+    println!("Header sum:   ${:02X}", header.header_checksum);
+    let global = if Header::global_checksum_of(&rom) == header.global_checksum {
+        "good"
+    } else {
+        "bad"
+    };
+    println!("Global sum:   ${:04X} ({})", header.global_checksum, global);
+}
+
+fn fail(error: &str) -> ! {
+    println!("ERR: {}\n", error);
+    println!("Usage: gbrust ROM");
+    println!("Where ROM is a Game Boy cartridge image, whose header is printed");
+    std::process::exit(1);
 }
@@ -0,0 +1,229 @@
+use cartridge::{self, Cartridge, NO_BANK};
+use header::NINTENDO_LOGO;
+
+/// The MBC1 mapper, for up to 2 MiB of ROM and 32 KiB of RAM
+/// BANK1 is a 5-bit register selecting the ROM bank at 0x4000-0x7FFF, and BANK2 a 2-bit
+/// register that supplies the bank bits above it. In mode 1, BANK2 also banks 0x0000-0x3FFF
+/// and the RAM. The MBC1M multicart wiring drops the top bit of BANK1, so BANK2 instead
+/// picks one of four 256 KiB games.
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    /// USAGE: Mbc1::new(ROM, RAM_SIZE)
+    /// Returns an MBC1 cartridge, wired as an MBC1M multicart if ROM looks like one
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = Mbc1::is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+    /// USAGE: Mbc1::is_multicart(ROM)
+    /// Returns whether ROM is an MBC1M collection, which nothing in the header says
+    /// Every game in one carries its own header, so the logo shows up again at bank 0x10
+    pub fn is_multicart(rom: &[u8]) -> bool {
+        rom.len() == 0x100000 && rom[0x40104..0x40134] == NINTENDO_LOGO[..]
+    }
+    /// Returns the ROM bank at ADDRESS, before wrapping to the size of the ROM
+    fn rom_bank(&self, address: u16) -> usize {
+        let (shift, bank1) = if self.multicart {
+            (4, self.bank1 & 0x0F)
+        } else {
+            (5, self.bank1)
+        };
+        let high = (self.bank2 as usize) << shift;
+        match address {
+            0x0000..=0x3FFF if self.mode => high,
+            0x0000..=0x3FFF => 0,
+            _ => high | bank1 as usize,
+        }
+    }
+    /// Returns the offset into ROM of ADDRESS, or None if there is no ROM at all
+    fn rom_offset(&self, address: u16) -> Option<usize> {
+        if self.rom.is_empty() {
+            return None;
+        }
+        let offset = self.rom_bank(address) * 0x4000 + (address as usize & 0x3FFF);
+        Some(offset % self.rom.len())
+    }
+    /// Returns the offset into RAM of ADDRESS, or None if RAM is missing or disabled
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        Some((bank * 0x2000 + (address as usize - 0xA000)) % self.ram.len())
+    }
+}
+
+impl Cartridge for Mbc1 {
+    fn read(&mut self, address: u16) -> u8 {
+        let byte = match address {
+            0x0000..=0x7FFF => self.rom_offset(address).map(|offset| self.rom[offset]),
+            _ => self.ram_offset(address).map(|offset| self.ram[offset]),
+        };
+        byte.unwrap_or(0xFF)
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Bank 0 cannot be selected here, so it reads as 1. Only all five bits being zero
+            // counts, which on small ROMs lets bank 0 through once the unused bits are masked.
+            0x2000..=0x3FFF => self.bank1 = match value & 0x1F {
+                0 => 1,
+                bank => bank,
+            },
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            0x6000..=0x7FFF => self.mode = value & 0x01 != 0,
+            _ => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = value;
+                }
+            }
+        }
+    }
+    fn bank(&self, address: u16) -> u16 {
+        let offset = match address {
+            0x0000..=0x7FFF => self.rom_offset(address).map(|offset| offset / 0x4000),
+            _ => self.ram_offset(address).map(|offset| offset / 0x2000),
+        };
+        offset.map(|bank| bank as u16).unwrap_or(NO_BANK)
+    }
+    fn mirror(&self, address: u16) -> u16 {
+        cartridge::ram_mirror(address, self.ram.len())
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn load_ram(&mut self, save: &[u8]) {
+        cartridge::copy_save(&mut self.ram, save);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cartridge::test::rom;
+
+    // Checks every ROM bank of a 2 MiB cartridge, and that bank 0 reads as bank 1
+    #[test]
+    fn mbc1_can_switch_rom_banks() {
+        let mut mbc = Mbc1::new(rom(128), 0);
+        assert_eq!((mbc.read(0x0000), mbc.read(0x4000)), (0, 1));
+        for bank in 0..128u8 {
+            mbc.write(0x2000, bank);
+            mbc.write(0x4000, bank >> 5);
+            let expected = if bank & 0x1F == 0 { bank + 1 } else { bank };
+            assert_eq!(mbc.read(0x4000), expected);
+            assert_eq!(mbc.bank(0x7FFF), expected as u16);
+            // Mode 0 keeps bank 0 at 0x0000 no matter what
+            assert_eq!(mbc.read(0x0000), 0);
+        }
+        // Only the low five bits of BANK1 and two of BANK2 exist
+        mbc.write(0x2000, 0xE3);
+        mbc.write(0x4000, 0xFD);
+        assert_eq!(mbc.read(0x4000), 0x23);
+    }
+    // Checks that mode 1 banks 0x0000-0x3FFF with BANK2
+    #[test]
+    fn mbc1_can_bank_rom_bank_0_in_mode_1() {
+        let mut mbc = Mbc1::new(rom(128), 0);
+        mbc.write(0x6000, 0x01);
+        for bank2 in 0..4u8 {
+            mbc.write(0x4000, bank2);
+            assert_eq!(mbc.read(0x0000), bank2 << 5);
+            assert_eq!(mbc.bank(0x0000), (bank2 as u16) << 5);
+        }
+        mbc.write(0x6000, 0x00);
+        assert_eq!(mbc.read(0x0000), 0);
+    }
+    // Checks that banks wrap to the size of smaller ROMs, letting bank 0 through at 0x4000
+    #[test]
+    fn mbc1_wraps_banks_to_rom_size() {
+        let mut mbc = Mbc1::new(rom(16), 0);
+        mbc.write(0x2000, 0x1F);
+        assert_eq!(mbc.read(0x4000), 0x0F);
+        mbc.write(0x2000, 0x10);
+        assert_eq!(mbc.read(0x4000), 0x00);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4000), 0x01);
+        // BANK2 is ignored entirely below 1 MiB
+        mbc.write(0x4000, 0x03);
+        assert_eq!(mbc.read(0x4000), 0x01);
+    }
+    // Checks RAM enable, and that only mode 1 banks RAM
+    #[test]
+    fn mbc1_can_enable_and_bank_ram() {
+        let mut mbc = Mbc1::new(rom(4), 0x8000);
+        mbc.write(0xA000, 0x42);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        assert_eq!(mbc.bank(0xA000), NO_BANK);
+        // Any value with 0xA in the low nibble enables RAM
+        mbc.write(0x0000, 0x1A);
+        for bank in 0..4u8 {
+            mbc.write(0x4000, bank);
+            mbc.write(0xA000, bank);
+        }
+        // In mode 0, every write went to bank 0
+        assert_eq!(mbc.read(0xA000), 3);
+        mbc.write(0x6000, 0x01);
+        for bank in 0..4u8 {
+            mbc.write(0x4000, bank);
+            mbc.write(0xBFFF, bank);
+            assert_eq!(mbc.bank(0xBFFF), bank as u16);
+        }
+        for bank in 0..4u8 {
+            mbc.write(0x4000, bank);
+            assert_eq!(mbc.read(0xBFFF), bank);
+        }
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xBFFF), 0xFF);
+        // A save holds every bank, and restores them even while RAM is disabled
+        let save = mbc.ram().to_vec();
+        assert_eq!((save.len(), save[0x2000 * 3 + 0x1FFF]), (0x8000, 3));
+        let mut restored = Mbc1::new(rom(4), 0x8000);
+        restored.load_ram(&save);
+        assert_eq!(restored.ram(), &save[..]);
+        // 2 KiB of RAM repeats across the whole 8 KiB window
+        let mut mbc = Mbc1::new(rom(4), 0x800);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x42);
+        assert_eq!(mbc.read(0xA800), 0x42);
+    }
+    // Checks the MBC1M wiring, where BANK2 picks one of four 256 KiB games
+    #[test]
+    fn mbc1_can_bank_multicarts() {
+        assert!(!Mbc1::is_multicart(&rom(64)));
+        let mut image = rom(64);
+        for game in 0..4 {
+            let start = game * 0x40000 + 0x0104;
+            image[start..start + 0x30].copy_from_slice(&NINTENDO_LOGO);
+        }
+        assert!(Mbc1::is_multicart(&image));
+        let mut mbc = Mbc1::new(image, 0);
+        for game in 0..4u8 {
+            mbc.write(0x4000, game);
+            mbc.write(0x2000, 0x01);
+            assert_eq!(mbc.read(0x4000), game << 4 | 0x01);
+            // The fifth bit of BANK1 is not wired, but still stops the 0 to 1 translation
+            mbc.write(0x2000, 0x10);
+            assert_eq!(mbc.read(0x4000), game << 4);
+            mbc.write(0x6000, 0x01);
+            assert_eq!(mbc.read(0x0000), game << 4);
+            mbc.write(0x6000, 0x00);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use cartridge::{self, Cartridge, NO_BANK};
+
+/// Size of the RAM built into the MBC2, in 4-bit cells
+pub const MBC2_RAM_SIZE: usize = 0x200;
+
+/// The MBC2 mapper, for up to 256 KiB of ROM, with 512 half-bytes of RAM built in
+/// Both of its registers sit in 0x0000-0x3FFF, and address bit 8 says which one is written.
+/// The RAM only has the low nibble of each byte, and repeats across 0xA000-0xBFFF.
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: [u8; MBC2_RAM_SIZE],
+    ram_enabled: bool,
+    bank: u8,
+}
+
+impl Mbc2 {
+    /// USAGE: Mbc2::new(ROM)
+    /// The header gives no RAM size for an MBC2, since the RAM is always there
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc2 {
+            rom,
+            ram: [0; MBC2_RAM_SIZE],
+            ram_enabled: false,
+            bank: 1,
+        }
+    }
+    /// Returns the offset into ROM of ADDRESS, or None if there is no ROM at all
+    fn rom_offset(&self, address: u16) -> Option<usize> {
+        if self.rom.is_empty() {
+            return None;
+        }
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.bank as usize,
+        };
+        Some((bank * 0x4000 + (address as usize & 0x3FFF)) % self.rom.len())
+    }
+    /// Returns the offset into RAM of ADDRESS, or None if RAM is disabled
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram_enabled {
+            Some(address as usize & (MBC2_RAM_SIZE - 1))
+        } else {
+            None
+        }
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF => self.rom_offset(address).map(|offset| self.rom[offset]),
+            // Nothing drives the upper four data lines, so they read as 1
+            _ => self.ram_offset(address).map(|offset| 0xF0 | self.ram[offset]),
+        }
+        .unwrap_or(0xFF)
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+            }
+            0x0000..=0x3FFF => {
+                self.bank = match value & 0x0F {
+                    0 => 1,
+                    bank => bank,
+                }
+            }
+            0x4000..=0x7FFF => {}
+            _ => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = value & 0x0F;
+                }
+            }
+        }
+    }
+    fn bank(&self, address: u16) -> u16 {
+        let offset = match address {
+            0x0000..=0x7FFF => self.rom_offset(address).map(|offset| offset / 0x4000),
+            _ => self.ram_offset(address).map(|_| 0),
+        };
+        offset.map(|bank| bank as u16).unwrap_or(NO_BANK)
+    }
+    fn mirror(&self, address: u16) -> u16 {
+        cartridge::ram_mirror(address, MBC2_RAM_SIZE)
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn load_ram(&mut self, save: &[u8]) {
+        for (cell, &byte) in self.ram.iter_mut().zip(save) {
+            *cell = byte & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cartridge::test::rom;
+
+    // Checks that address bit 8 picks the ROM bank register over RAM enable
+    #[test]
+    fn mbc2_can_switch_rom_banks() {
+        let mut mbc = Mbc2::new(rom(16));
+        assert_eq!((mbc.read(0x0000), mbc.read(0x4000)), (0, 1));
+        for bank in 0..0x20u8 {
+            mbc.write(0x2100, bank);
+            let expected = match bank & 0x0F {
+                0 => 1,
+                bank => bank,
+            };
+            assert_eq!(mbc.read(0x4000), expected);
+            assert_eq!(mbc.bank(0x7FFF), expected as u16);
+        }
+        // Bit 8 clear is RAM enable, whatever the rest of the address
+        mbc.write(0x2000, 0x03);
+        assert_eq!(mbc.read(0x4000), 0x0F);
+        // Any other address with bit 8 set works too, and 0x4000-0x7FFF does nothing
+        mbc.write(0x01FF, 0x03);
+        mbc.write(0x4100, 0x05);
+        assert_eq!(mbc.read(0x4000), 0x03);
+        // Smaller ROMs wrap
+        let mut mbc = Mbc2::new(rom(4));
+        mbc.write(0x0100, 0x07);
+        assert_eq!(mbc.read(0x4000), 0x03);
+    }
+    // Checks RAM enable, and that the 512 nibbles repeat with their high bits set
+    #[test]
+    fn mbc2_can_enable_half_byte_ram() {
+        let mut mbc = Mbc2::new(rom(2));
+        mbc.write(0xA000, 0x05);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        assert_eq!(mbc.bank(0xA000), NO_BANK);
+        // Bit 8 set is the ROM bank register, so this leaves RAM disabled
+        mbc.write(0x0100, 0x0A);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        mbc.write(0x3EFF, 0x0A);
+        mbc.write(0xA000, 0x35);
+        mbc.write(0xA1FF, 0xC9);
+        for echo in (0xA000..0xC000).step_by(MBC2_RAM_SIZE) {
+            assert_eq!(mbc.read(echo), 0xF5);
+            assert_eq!(mbc.read(echo + 0x1FF), 0xF9);
+            assert_eq!(mbc.mirror(echo + 0x1FF), 0xA1FF);
+        }
+        assert_eq!(mbc.bank(0xBFFF), 0);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+    }
+    // Checks that the RAM saves as 512 bytes, one nibble in each
+    #[test]
+    fn mbc2_can_save_ram() {
+        let mut mbc = Mbc2::new(rom(2));
+        mbc.write(0x0000, 0x0A);
+        for address in 0xA000..0xA200u16 {
+            mbc.write(address, address as u8);
+        }
+        let save = mbc.ram().to_vec();
+        assert_eq!(save.len(), MBC2_RAM_SIZE);
+        assert_eq!((save[0x00], save[0x1F]), (0x00, 0x0F));
+        let mut restored = Mbc2::new(rom(2));
+        restored.load_ram(&[0xFF; MBC2_RAM_SIZE]);
+        restored.load_ram(&save);
+        restored.write(0x0000, 0x0A);
+        assert_eq!(restored.read(0xB01F), 0xFF);
+        assert_eq!(restored.read(0xB020), 0xF0);
+        assert_eq!(restored.ram(), &save[..]);
+    }
+}
@@ -0,0 +1,221 @@
+use cartridge::{self, Cartridge, NO_BANK};
+use rtc::{Rtc, RTC_DH, RTC_S};
+
+/// The MBC3 mapper, for up to 2 MiB of ROM, 32 KiB of RAM and optionally a real-time clock
+/// The RAM bank register at 0x4000-0x5FFF either picks a RAM bank or maps a clock register
+/// at 0xA000-0xBFFF. RAM enable covers both.
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    enabled: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    latch: u8,
+}
+
+impl Mbc3 {
+    /// USAGE: Mbc3::new(ROM, RAM_SIZE, TIMER) where TIMER is whether there is a clock
+    pub fn new(rom: Vec<u8>, ram_size: usize, timer: bool) -> Self {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            rtc: if timer { Some(Rtc::new()) } else { None },
+            enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            // Anything but 0x00, so the first write of 0x01 does not latch
+            latch: 0xFF,
+        }
+    }
+    /// Returns the offset into ROM of ADDRESS, or None if there is no ROM at all
+    fn rom_offset(&self, address: u16) -> Option<usize> {
+        if self.rom.is_empty() {
+            return None;
+        }
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        Some((bank * 0x4000 + (address as usize & 0x3FFF)) % self.rom.len())
+    }
+    /// Returns the offset into RAM of ADDRESS, or None if RAM is missing, disabled or unmapped
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.enabled || self.ram.is_empty() || self.ram_bank > 0x03 {
+            return None;
+        }
+        let offset = self.ram_bank as usize * 0x2000 + (address as usize - 0xA000);
+        Some(offset % self.ram.len())
+    }
+    /// Returns the clock, if it is there, enabled and mapped
+    fn mapped_rtc(&mut self) -> Option<&mut Rtc> {
+        match self.ram_bank {
+            RTC_S..=RTC_DH if self.enabled => self.rtc.as_mut(),
+            _ => None,
+        }
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF => self.rom_offset(address).map(|offset| self.rom[offset]),
+            _ => match self.ram_offset(address) {
+                Some(offset) => Some(self.ram[offset]),
+                None => {
+                    let register = self.ram_bank;
+                    self.mapped_rtc().map(|rtc| rtc.read(register))
+                }
+            },
+        }
+        .unwrap_or(0xFF)
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = match value & 0x7F {
+                    0 => 1,
+                    bank => bank,
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = value,
+            0x6000..=0x7FFF => {
+                if self.latch == 0x00 && value == 0x01 {
+                    if let Some(rtc) = self.rtc.as_mut() {
+                        rtc.latch();
+                    }
+                }
+                self.latch = value;
+            }
+            _ => match self.ram_offset(address) {
+                Some(offset) => self.ram[offset] = value,
+                None => {
+                    let register = self.ram_bank;
+                    if let Some(rtc) = self.mapped_rtc() {
+                        rtc.write(register, value);
+                    }
+                }
+            },
+        }
+    }
+    fn bank(&self, address: u16) -> u16 {
+        let offset = match address {
+            0x0000..=0x7FFF => self.rom_offset(address).map(|offset| offset / 0x4000),
+            _ => self.ram_offset(address).map(|offset| offset / 0x2000),
+        };
+        offset.map(|bank| bank as u16).unwrap_or(NO_BANK)
+    }
+    fn mirror(&self, address: u16) -> u16 {
+        cartridge::ram_mirror(address, self.ram.len())
+    }
+    fn tick(&mut self) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.tick();
+        }
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn load_ram(&mut self, save: &[u8]) {
+        cartridge::copy_save(&mut self.ram, save);
+    }
+    fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cartridge::test::rom;
+    use rtc::{CYCLES_PER_SECOND, RTC_DL, RTC_H, RTC_M};
+
+    // Checks all seven bits of the ROM bank, and that bank 0 reads as bank 1
+    #[test]
+    fn mbc3_can_switch_rom_banks() {
+        let mut mbc = Mbc3::new(rom(128), 0, false);
+        assert_eq!((mbc.read(0x0000), mbc.read(0x4000)), (0, 1));
+        for bank in 0..0x100u16 {
+            mbc.write(0x2000, bank as u8);
+            let expected = match bank & 0x7F {
+                0 => 1,
+                bank => bank,
+            };
+            assert_eq!(mbc.read(0x4000), expected as u8);
+            assert_eq!(mbc.bank(0x7FFF), expected);
+            assert_eq!(mbc.read(0x0000), 0);
+        }
+    }
+    // Checks RAM enable and all four RAM banks
+    #[test]
+    fn mbc3_can_enable_and_bank_ram() {
+        let mut mbc = Mbc3::new(rom(4), 0x8000, false);
+        mbc.write(0xA000, 0x42);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        mbc.write(0x0000, 0x0A);
+        for bank in 0..4u8 {
+            mbc.write(0x4000, bank);
+            mbc.write(0xA000, bank + 0x10);
+            assert_eq!(mbc.bank(0xA000), bank as u16);
+        }
+        for bank in 0..4u8 {
+            mbc.write(0x4000, bank);
+            assert_eq!(mbc.read(0xA000), bank + 0x10);
+        }
+        assert_eq!(mbc.ram()[0x6000], 0x13);
+        // Without a clock, its registers leave the bus floating
+        mbc.write(0x4000, RTC_S);
+        mbc.write(0xA000, 0x12);
+        assert_eq!((mbc.read(0xA000), mbc.bank(0xA000)), (0xFF, NO_BANK));
+        mbc.write(0x4000, 0x00);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+    }
+    // Checks that the clock registers are mapped in, and latched by writing 0x00 then 0x01
+    #[test]
+    fn mbc3_can_latch_the_clock() {
+        let mut mbc = Mbc3::new(rom(4), 0x2000, true);
+        mbc.write(0x0000, 0x0A);
+        for &(register, value) in [(RTC_S, 5), (RTC_M, 4), (RTC_H, 3), (RTC_DL, 2)].iter() {
+            mbc.write(0x4000, register);
+            mbc.write(0xA000, value);
+        }
+        mbc.write(0x4000, RTC_S);
+        assert_eq!(mbc.read(0xA000), 0);
+        // 0x01 alone, or after anything but 0x00, does not latch
+        mbc.write(0x6000, 0x01);
+        mbc.write(0x6000, 0x02);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0xA000), 0);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        let latched: Vec<u8> = (RTC_S..=RTC_DH)
+            .map(|register| {
+                mbc.write(0x4000, register);
+                mbc.read(0xBFFF)
+            })
+            .collect();
+        assert_eq!(latched, [5, 4, 3, 2, 0]);
+        // Cartridge time runs from emulated cycles, seen at the next latch
+        for _ in 0..CYCLES_PER_SECOND {
+            mbc.tick();
+        }
+        mbc.write(0x4000, RTC_S);
+        assert_eq!(mbc.read(0xA000), 5);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0xA000), 6);
+        // RAM is still there, and disabling RAM hides the clock too
+        mbc.write(0x4000, 0x00);
+        mbc.write(0xA000, 0x42);
+        assert_eq!(mbc.read(0xA000), 0x42);
+        mbc.write(0x4000, RTC_S);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        assert_eq!(mbc.rtc().unwrap().registers(), [6, 4, 3, 2, 0]);
+    }
+}
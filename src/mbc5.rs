@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+
+use cartridge::{self, Cartridge, RumbleEvent, NO_BANK};
+
+/// How many rumble events are kept for cart.rumble_events() before the oldest are dropped
+pub const MAX_RUMBLE_EVENTS: usize = 1024;
+
+/// The MBC5 mapper, for up to 8 MiB of ROM and 128 KiB of RAM
+/// The ROM bank number is 9 bits, split over 0x2000-0x2FFF and 0x3000-0x3FFF, and unlike
+/// earlier mappers bank 0 can be mapped at 0x4000-0x7FFF too. On rumble cartridges, bit 3 of
+/// the RAM bank register drives the motor instead of the RAM.
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    rumble: Option<bool>,
+    events: VecDeque<RumbleEvent>,
+    cycle: u64,
+}
+
+impl Mbc5 {
+    /// USAGE: Mbc5::new(ROM, RAM_SIZE, RUMBLE) where RUMBLE is whether there is a motor
+    pub fn new(rom: Vec<u8>, ram_size: usize, rumble: bool) -> Self {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble: if rumble { Some(false) } else { None },
+            events: VecDeque::new(),
+            cycle: 0,
+        }
+    }
+    /// USAGE: mbc.rumbling()
+    /// Returns whether the motor is running, which is never without one
+    pub fn rumbling(&self) -> bool {
+        self.rumble == Some(true)
+    }
+    /// Returns the offset into ROM of ADDRESS, or None if there is no ROM at all
+    fn rom_offset(&self, address: u16) -> Option<usize> {
+        if self.rom.is_empty() {
+            return None;
+        }
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+        Some((bank * 0x4000 + (address as usize & 0x3FFF)) % self.rom.len())
+    }
+    /// Returns the offset into RAM of ADDRESS, or None if RAM is missing or disabled
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let offset = self.ram_bank as usize * 0x2000 + (address as usize - 0xA000);
+        Some(offset % self.ram.len())
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn read(&mut self, address: u16) -> u8 {
+        let byte = match address {
+            0x0000..=0x7FFF => self.rom_offset(address).map(|offset| self.rom[offset]),
+            _ => self.ram_offset(address).map(|offset| self.ram[offset]),
+        };
+        byte.unwrap_or(0xFF)
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            // Only 0x0A enables RAM, not just anything with 0xA in the low nibble
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = self.rom_bank & 0x100 | u16::from(value),
+            0x3000..=0x3FFF => self.rom_bank = self.rom_bank & 0xFF | u16::from(value & 1) << 8,
+            0x4000..=0x5FFF => match self.rumble {
+                Some(rumbling) => {
+                    self.ram_bank = value & 0x07;
+                    let on = value & 0x08 != 0;
+                    if on != rumbling {
+                        self.rumble = Some(on);
+                        if self.events.len() == MAX_RUMBLE_EVENTS {
+                            self.events.pop_front();
+                        }
+                        self.events.push_back(RumbleEvent {
+                            cycle: self.cycle,
+                            on,
+                        });
+                    }
+                }
+                None => self.ram_bank = value & 0x0F,
+            },
+            0x6000..=0x7FFF => {}
+            _ => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = value;
+                }
+            }
+        }
+    }
+    fn bank(&self, address: u16) -> u16 {
+        let offset = match address {
+            0x0000..=0x7FFF => self.rom_offset(address).map(|offset| offset / 0x4000),
+            _ => self.ram_offset(address).map(|offset| offset / 0x2000),
+        };
+        offset.map(|bank| bank as u16).unwrap_or(NO_BANK)
+    }
+    fn mirror(&self, address: u16) -> u16 {
+        cartridge::ram_mirror(address, self.ram.len())
+    }
+    fn tick(&mut self) {
+        self.cycle += 1;
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn load_ram(&mut self, save: &[u8]) {
+        cartridge::copy_save(&mut self.ram, save);
+    }
+    fn rumble_events(&mut self) -> Vec<RumbleEvent> {
+        self.events.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cartridge::test::rom;
+
+    /// Returns the bank number at the start of 0x4000-0x7FFF
+    fn upper_bank(mbc: &mut Mbc5) -> u16 {
+        u16::from(mbc.read(0x4000)) | u16::from(mbc.read(0x4001)) << 8
+    }
+
+    // Checks all nine bits of the ROM bank, including bank 0
+    #[test]
+    fn mbc5_can_switch_rom_banks() {
+        let mut mbc = Mbc5::new(rom(512), 0, false);
+        assert_eq!(upper_bank(&mut mbc), 1);
+        for bank in 0..512u16 {
+            mbc.write(0x2000, bank as u8);
+            mbc.write(0x3000, (bank >> 8) as u8);
+            assert_eq!(upper_bank(&mut mbc), bank);
+            assert_eq!(mbc.bank(0x4000), bank);
+            assert_eq!(mbc.read(0x0000), 0);
+        }
+        // The two halves are set separately, and only bit 0 of the high one exists
+        mbc.write(0x3000, 0xFE);
+        assert_eq!(upper_bank(&mut mbc), 0x0FF);
+        mbc.write(0x2FFF, 0x00);
+        assert_eq!(upper_bank(&mut mbc), 0x000);
+        mbc.write(0x3FFF, 0x01);
+        assert_eq!(upper_bank(&mut mbc), 0x100);
+        // Smaller ROMs wrap
+        let mut mbc = Mbc5::new(rom(64), 0, false);
+        mbc.write(0x2000, 0x41);
+        assert_eq!(upper_bank(&mut mbc), 0x01);
+    }
+    // Checks RAM enable and all sixteen RAM banks
+    #[test]
+    fn mbc5_can_enable_and_bank_ram() {
+        let mut mbc = Mbc5::new(rom(4), 0x20000, false);
+        mbc.write(0x0000, 0x1A);
+        mbc.write(0xA000, 0x42);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        assert_eq!(mbc.bank(0xA000), NO_BANK);
+        mbc.write(0x0000, 0x0A);
+        for bank in 0..16u8 {
+            mbc.write(0x4000, bank);
+            mbc.write(0xBFFF, bank + 0x10);
+            assert_eq!(mbc.bank(0xBFFF), bank as u16);
+        }
+        for bank in 0..16u8 {
+            mbc.write(0x4000, bank | 0xF0);
+            assert_eq!(mbc.read(0xBFFF), bank + 0x10);
+        }
+        assert_eq!(mbc.ram()[0x1FFFF], 0x1F);
+        assert!(mbc.rumble_events().is_empty());
+    }
+    // Checks that RAM bank bit 3 runs the motor, and that each change is reported once
+    #[test]
+    fn mbc5_reports_rumble() {
+        let mut mbc = Mbc5::new(rom(4), 0x10000, true);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x0F);
+        assert!(mbc.rumbling());
+        // With a motor, bit 3 no longer banks RAM
+        mbc.write(0xA000, 0x42);
+        mbc.write(0x4000, 0x07);
+        assert_eq!(mbc.read(0xA000), 0x42);
+        assert_eq!(mbc.bank(0xA000), 7);
+        for _ in 0..100 {
+            mbc.tick();
+        }
+        mbc.write(0x4000, 0x00);
+        mbc.write(0x4000, 0x08);
+        let events = mbc.rumble_events();
+        assert_eq!(
+            events,
+            [
+                RumbleEvent { cycle: 0, on: true },
+                RumbleEvent { cycle: 0, on: false },
+                RumbleEvent { cycle: 100, on: true },
+            ]
+        );
+        assert!(mbc.rumble_events().is_empty());
+        // Only the newest events are kept until they are taken
+        for i in 0..MAX_RUMBLE_EVENTS + 1 {
+            mbc.tick();
+            mbc.write(0x4000, if i % 2 == 0 { 0x00 } else { 0x08 });
+        }
+        let events = mbc.rumble_events();
+        assert_eq!(events.len(), MAX_RUMBLE_EVENTS);
+        assert_eq!(events[0], RumbleEvent { cycle: 102, on: true });
+        assert!(!mbc.rumbling());
+    }
+}
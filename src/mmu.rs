@@ -0,0 +1,364 @@
+use boot::BootRom;
+use bus::Bus;
+use cartridge::Cartridge;
+use model::Model;
+
+/// Bits of each IO register from 0xFF00 to 0xFF7F that always read as 1 on the DMG
+/// Registers that do not exist at all read as 0xFF, and so do write-only ones
+static UNUSED_BITS: [u8; 0x80] = [
+    // P1   SB    SC          DIV   TIMA  TMA   TAC                                         IF
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // NR10 NR11  NR12  NR13  NR14        NR21  NR22  NR23  NR24  NR30  NR31  NR32  NR33  NR34
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41 NR42  NR43  NR44  NR50  NR51  NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC STAT  SCY   SCX   LY    LYC   DMA   BGP   OBP0  OBP1  WY    WX
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/// Address of the register that unmaps the boot ROM for good once written
+pub const BOOT: u16 = 0xFF50;
+
+/// Bank reported for 0x0000-0x00FF while the boot ROM is mapped over the cartridge
+pub const BOOT_BANK: u16 = 0xFFFF;
+
+/// The Game Boy memory map, routing each address to the region or register behind it
+/// Owns everything in the address space except the cartridge's own ROM and RAM, which it
+/// forwards to. The CPU attaches to it as its bus.
+pub struct Mmu {
+    model: Model,
+    cartridge: Box<dyn Cartridge>,
+    // The boot ROM, for as long as it is mapped
+    boot: Option<BootRom>,
+    vram: Vec<u8>,
+    wram: Vec<u8>,
+    oam: Vec<u8>,
+    io: [u8; 0x80],
+    hram: [u8; 0x7F],
+    ie: u8,
+}
+
+impl Mmu {
+    /// USAGE: Mmu::new(MODEL, CART)
+    /// Returns the memory map of MODEL as it powers on, with CART inserted
+    pub fn new(model: Model, cartridge: Box<dyn Cartridge>) -> Self {
+        Mmu {
+            model,
+            cartridge,
+            boot: None,
+            vram: vec![0; 0x2000],
+            wram: vec![0; 0x2000],
+            oam: vec![0; 0xA0],
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            ie: 0,
+        }
+    }
+    /// USAGE: Mmu::post_boot(MODEL, CART)
+    /// Returns the memory map with the IO registers as MODEL's boot ROM leaves them
+    /// Pair it with CPU::post_boot() to start a cartridge at 0x0100 without a boot ROM
+    pub fn post_boot(model: Model, cartridge: Box<dyn Cartridge>) -> Self {
+        let mut mmu = Mmu::new(model, cartridge);
+        for &(address, value) in model.io().iter() {
+            mmu.write(address, value);
+        }
+        mmu
+    }
+    /// USAGE: Mmu::with_boot_rom(CART, BOOT)
+    /// Returns the memory map as it powers on, with BOOT mapped over 0x0000-0x00FF until a
+    /// non-zero write to FF50. Start the CPU from CPU::new() to run the boot sequence.
+    pub fn with_boot_rom(cartridge: Box<dyn Cartridge>, boot: BootRom) -> Self {
+        let mut mmu = Mmu::new(boot.model(), cartridge);
+        mmu.boot = Some(boot);
+        mmu
+    }
+    /// USAGE: self.booting()
+    /// Returns whether the boot ROM is still mapped
+    pub fn booting(&self) -> bool {
+        self.boot.is_some()
+    }
+    /// USAGE: self.model()
+    /// Returns the model whose memory map this is
+    pub fn model(&self) -> Model {
+        self.model
+    }
+    /// USAGE: self.cartridge()
+    /// Returns the cartridge in the slot, e.g. to take its RAM for a save
+    pub fn cartridge(&self) -> &dyn Cartridge {
+        &*self.cartridge
+    }
+    /// USAGE: self.cartridge_mut()
+    /// Returns the cartridge in the slot, e.g. to load a save into its RAM
+    pub fn cartridge_mut(&mut self) -> &mut dyn Cartridge {
+        &mut *self.cartridge
+    }
+    /// Returns what a read of the unusable region at 0xFEA0-0xFEFF gives
+    /// The DMG, MGB and SGB read 0x00 there. The CGB, from revision E on, repeats the high
+    /// nibble of the low address byte, so 0xFEA0-0xFEAF reads 0xAA and so on.
+    fn unusable(&self, address: u16) -> u8 {
+        match self.model {
+            Model::Cgb => (address as u8 & 0xF0) | (address as u8 >> 4),
+            _ => 0x00,
+        }
+    }
+    /// Returns the bits of the IO register at ADDRESS that always read as 1
+    /// The CGB has registers of its own in what is unmapped space on the other models
+    fn unused_bits(&self, address: u16) -> u8 {
+        let dmg = UNUSED_BITS[address as usize - 0xFF00];
+        if self.model != Model::Cgb {
+            return dmg;
+        }
+        match address {
+            0xFF02 => 0x7C,                   // SC, with the clock speed bit
+            0xFF4D => 0x7E,                   // KEY1
+            0xFF4F => 0xFE,                   // VBK
+            0xFF56 => 0x3C,                   // RP
+            0xFF68 | 0xFF6A => 0x40,          // BCPS and OCPS
+            0xFF69 | 0xFF6B => 0x00,          // BCPD and OCPD
+            0xFF6C => 0xFE,                   // OPRI
+            0xFF70 => 0xF8,                   // SVBK
+            0xFF72..=0xFF74 => 0x00,          // Undocumented
+            0xFF75 => 0x8F,                   // Undocumented
+            _ => dmg,
+        }
+    }
+}
+
+impl Bus for Mmu {
+    fn read(&mut self, address: u16) -> u8 {
+        if let Some(ref boot) = self.boot {
+            if address <= 0x00FF {
+                return boot.read(address);
+            }
+        }
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.read(address),
+            0x8000..=0x9FFF => self.vram[address as usize - 0x8000],
+            0xC000..=0xDFFF => self.wram[address as usize - 0xC000],
+            // Echo RAM mirrors 0xC000-0xDDFF
+            0xE000..=0xFDFF => self.wram[address as usize - 0xE000],
+            0xFE00..=0xFE9F => self.oam[address as usize - 0xFE00],
+            0xFEA0..=0xFEFF => self.unusable(address),
+            0xFF00..=0xFF7F => self.io[address as usize - 0xFF00] | self.unused_bits(address),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80],
+            _ => self.ie,
+        }
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.write(address, value),
+            0x8000..=0x9FFF => self.vram[address as usize - 0x8000] = value,
+            0xC000..=0xDFFF => self.wram[address as usize - 0xC000] = value,
+            0xE000..=0xFDFF => self.wram[address as usize - 0xE000] = value,
+            0xFE00..=0xFE9F => self.oam[address as usize - 0xFE00] = value,
+            // Writes to the unusable region go nowhere
+            0xFEA0..=0xFEFF => {}
+            // The boot ROM unmaps itself as its last act, and can never be mapped back
+            BOOT if value != 0 => self.boot = None,
+            0xFF00..=0xFF7F => self.io[address as usize - 0xFF00] = value,
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80] = value,
+            _ => self.ie = value,
+        }
+    }
+    fn tick(&mut self) {
+        self.cartridge.tick();
+    }
+    fn bank(&self, address: u16) -> u16 {
+        match address {
+            0x0000..=0x00FF if self.boot.is_some() => BOOT_BANK,
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.bank(address),
+            _ => 0,
+        }
+    }
+    fn mirror(&self, address: u16) -> u16 {
+        match address {
+            0xA000..=0xBFFF => self.cartridge.mirror(address),
+            0xE000..=0xFDFF => address - 0x2000,
+            _ => address,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cartridge::RomOnly;
+    use cpu::{Mode, CPU};
+
+    const MODELS: [Model; 5] = [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb];
+
+    fn rom_only(model: Model) -> Mmu {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0x12;
+        rom[0x7FFF] = 0x34;
+        Mmu::new(model, Box::new(RomOnly::new(rom, 0)))
+    }
+
+    // Checks that every RAM region keeps what is written to it, and ROM does not
+    #[test]
+    fn mmu_can_read_and_write_every_region() {
+        let mut mmu = rom_only(Model::Dmg);
+        assert_eq!((mmu.read(0x0000), mmu.read(0x7FFF)), (0x12, 0x34));
+        mmu.write(0x0000, 0x56);
+        assert_eq!(mmu.read(0x0000), 0x12);
+        for &address in [0x8000, 0x9FFF, 0xC000, 0xDFFF, 0xFE00, 0xFE9F, 0xFF80, 0xFFFE, 0xFFFF]
+            .iter()
+        {
+            mmu.write(address, 0xA5);
+            assert_eq!(mmu.read(address), 0xA5, "{:#06X}", address);
+        }
+        // A cartridge without RAM leaves the bus floating
+        mmu.write(0xA000, 0x00);
+        assert_eq!((mmu.read(0xA000), mmu.read(0xBFFF)), (0xFF, 0xFF));
+        let mut mmu = Mmu::new(Model::Dmg, Box::new(RomOnly::new(vec![], 0x2000)));
+        mmu.write(0xBFFF, 0x42);
+        assert_eq!((mmu.read(0x0000), mmu.read(0xBFFF)), (0xFF, 0x42));
+        // Cartridge RAM can be saved and restored from outside
+        assert_eq!(mmu.cartridge().ram()[0x1FFF], 0x42);
+        mmu.cartridge_mut().load_ram(&[0x24; 0x2000]);
+        assert_eq!(mmu.read(0xBFFF), 0x24);
+    }
+    // Checks that echo RAM mirrors 0xC000-0xDDFF both ways
+    #[test]
+    fn mmu_mirrors_echo_ram() {
+        let mut mmu = rom_only(Model::Dmg);
+        for offset in 0..0x1E00u16 {
+            mmu.write(0xC000 + offset, offset as u8);
+            assert_eq!(mmu.read(0xE000 + offset), offset as u8);
+            mmu.write(0xE000 + offset, !offset as u8);
+            assert_eq!(mmu.read(0xC000 + offset), !offset as u8);
+            assert_eq!(mmu.mirror(0xE000 + offset), 0xC000 + offset);
+        }
+        // 0xDE00-0xDFFF has no echo, 0xFE00 on is OAM
+        mmu.write(0xFE00, 0x55);
+        mmu.write(0xDE00, 0x77);
+        assert_eq!((mmu.read(0xFE00), mmu.read(0xDE00)), (0x55, 0x77));
+        assert_eq!((mmu.mirror(0xFE00), mmu.mirror(0xDE00)), (0xFE00, 0xDE00));
+    }
+    // Checks what each model reads from the unusable region, which ignores writes
+    #[test]
+    fn mmu_reads_unusable_region_per_model() {
+        for model in MODELS.iter() {
+            let mut mmu = rom_only(*model);
+            for address in 0xFEA0..=0xFEFFu16 {
+                mmu.write(address, 0x42);
+                let expected = match *model {
+                    Model::Cgb => (address as u8 >> 4) * 0x11,
+                    _ => 0x00,
+                };
+                assert_eq!(mmu.read(address), expected, "{:?} {:#06X}", model, address);
+            }
+        }
+    }
+    // Checks that unused IO bits read as 1, and unmapped IO reads as 0xFF
+    #[test]
+    fn mmu_reads_unused_io_bits_as_set() {
+        let mut mmu = rom_only(Model::Dmg);
+        for address in 0xFF00..=0xFF7Fu16 {
+            mmu.write(address, 0x00);
+        }
+        let reads = [
+            (0xFF00, 0xC0),
+            (0xFF03, 0xFF),
+            (0xFF07, 0xF8),
+            (0xFF0F, 0xE0),
+            (0xFF13, 0xFF),
+            (0xFF26, 0x70),
+            (0xFF30, 0x00),
+            (0xFF41, 0x80),
+            (0xFF44, 0x00),
+            (0xFF4F, 0xFF),
+            (0xFF50, 0xFF),
+            (0xFF70, 0xFF),
+        ];
+        for &(address, value) in reads.iter() {
+            assert_eq!(mmu.read(address), value, "{:#06X}", address);
+        }
+        // IE has no unused bits
+        mmu.write(0xFFFF, 0x00);
+        assert_eq!(mmu.read(0xFFFF), 0x00);
+        // The CGB-only registers only exist on the CGB
+        let mut mmu = rom_only(Model::Cgb);
+        mmu.write(0xFF70, 0x00);
+        assert_eq!((mmu.read(0xFF70), mmu.read(0xFF02)), (0xF8, 0x7C));
+    }
+    // Checks that every IO register reads back as the boot ROM left it
+    #[test]
+    fn mmu_reads_post_boot_io() {
+        for model in MODELS.iter() {
+            let mut mmu = Mmu::post_boot(*model, Box::new(RomOnly::new(vec![], 0)));
+            for &(address, value) in model.io().iter() {
+                assert_eq!(mmu.read(address), value, "{:?} {:#06X}", model, address);
+            }
+        }
+    }
+    // Checks that the CPU can run a cartridge through the memory map
+    #[test]
+    fn mmu_runs_cartridge() {
+        let rom = include_bytes!("../tetris.gb").to_vec();
+        let checksum = rom[0x014D];
+        let mmu = Mmu::post_boot(Model::Dmg, Box::new(RomOnly::new(rom, 0)));
+        let mut cpu = CPU::post_boot(mmu, Model::Dmg, checksum);
+        for address in 0xD000..=0xDFFFu16 {
+            cpu.bus_mut().write(address, 0xFF);
+        }
+        // Tetris clears 0xD000-0xDFFF, sets up IO and then waits for LY to reach 0x94,
+        // which it never does without a PPU
+        for _ in 0..100_000 {
+            cpu.step().unwrap();
+        }
+        assert!((0x02B2..=0x02B7).contains(&cpu.registers().pc));
+        let mmu = cpu.bus_mut();
+        assert!((0xD000..=0xDFFFu16).all(|address| mmu.read(address) == 0x00));
+        assert_eq!(mmu.read(0xF000), 0x00);
+        assert_eq!((mmu.read(0xFFFF), mmu.read(0xFF0F)), (0x01, 0xE1));
+        assert_eq!((mmu.read(0xFF02), mmu.read(0xFF40)), (0x7E, 0x80));
+    }
+    // Checks that the boot ROM hides the cartridge header's start until FF50 is written
+    #[test]
+    fn mmu_maps_boot_rom_until_ff50() {
+        let boot = BootRom::new(include_bytes!("../DMG_ROM.bin")).unwrap();
+        let rom = vec![0x42; 0x8000];
+        let mut mmu = Mmu::with_boot_rom(Box::new(RomOnly::new(rom, 0)), boot);
+        assert!(mmu.booting());
+        assert_eq!((mmu.read(0x0000), mmu.read(0x00FF), mmu.read(0x0100)), (0x31, 0x50, 0x42));
+        assert_eq!((mmu.bank(0x00FF), mmu.bank(0x0100)), (BOOT_BANK, 0));
+        // Writing zero leaves it mapped
+        mmu.write(BOOT, 0x00);
+        assert_eq!(mmu.read(0x0000), 0x31);
+        mmu.write(BOOT, 0x01);
+        assert!(!mmu.booting());
+        assert_eq!((mmu.read(0x0000), mmu.read(BOOT)), (0x42, 0xFF));
+        assert_eq!(mmu.bank(0x0000), 0);
+    }
+    // Checks that the real boot sequence accepts Tetris and leaves the documented state
+    #[test]
+    fn mmu_boots_cartridge_through_boot_rom() {
+        let boot = BootRom::new(include_bytes!("../DMG_ROM.bin")).unwrap();
+        let rom = include_bytes!("../tetris.gb").to_vec();
+        let checksum = rom[0x014D];
+        let mmu = Mmu::with_boot_rom(Box::new(RomOnly::new(rom, 0)), boot);
+        let mut cpu = CPU::new(mmu);
+        cpu.set_block_cache(true);
+        // With no PPU yet, every wait for VBlank ends at once if LY sits at 0x90
+        cpu.bus_mut().write(0xFF44, 0x90);
+        let mut steps = 0;
+        while cpu.bus().booting() {
+            cpu.step().unwrap();
+            steps += 1;
+            assert!(steps < 1_000_000, "Stuck at {:#06X}", cpu.registers().pc);
+        }
+        let registers = Model::Dmg.registers(checksum);
+        assert_eq!(cpu.registers().to_string(), registers.to_string());
+        assert_eq!(cpu.registers().mode, Mode::Running);
+        // The logo is unpacked into VRAM, tile 1 holding the top left of the N
+        assert_eq!(cpu.bus_mut().read(0x8010), 0xF0);
+        assert_eq!(cpu.bus_mut().read(0x9910), 0x19);
+        assert_eq!(cpu.bus_mut().read(0xFF40), 0x91);
+    }
+}
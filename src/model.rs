@@ -0,0 +1,185 @@
+use cpu::{Mode, Registers};
+
+/// The Game Boy models whose boot ROMs leave the machine in different states
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    /// The original DMG with the early DMG0 boot ROM
+    Dmg0,
+    /// The original Game Boy
+    Dmg,
+    /// The Game Boy Pocket
+    Mgb,
+    /// The Super Game Boy
+    Sgb,
+    /// The Game Boy Color, running a color game
+    Cgb,
+}
+
+/// IO registers every model's boot ROM leaves with the same value, as (address, value)
+static COMMON_IO: [(u16, u8); 34] = [
+    (0xFF00, 0xCF), // P1
+    (0xFF01, 0x00), // SB
+    (0xFF05, 0x00), // TIMA
+    (0xFF06, 0x00), // TMA
+    (0xFF07, 0xF8), // TAC
+    (0xFF0F, 0xE1), // IF
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0xBF), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0xBF), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0xBF), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0xBF), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF45, 0x00), // LYC
+    (0xFF47, 0xFC), // BGP
+    (0xFF4A, 0x00), // WY
+    (0xFF4B, 0x00), // WX
+    (0xFFFF, 0x00), // IE
+];
+
+/// Address of the divider register
+pub const DIV: u16 = 0xFF04;
+
+impl Model {
+    /// USAGE: model.registers(CHECKSUM) where CHECKSUM is the header checksum at 0x014D
+    /// Returns the CPU registers as the model's boot ROM leaves them, with PC at 0x0100
+    /// The DMG and MGB boot ROMs leave H and C set unless the header checksum is zero
+    pub fn registers(self, header_checksum: u8) -> Registers {
+        let checksum_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+        let (a, f, b, c, d, e, h, l) = match self {
+            Model::Dmg0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            Model::Dmg => (0x01, checksum_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, checksum_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+        };
+        Registers {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: 0xFFFE,
+            pc: 0x0100,
+            ime: false,
+            mode: Mode::Running,
+        }
+    }
+    /// USAGE: model.div()
+    /// Returns DIV as the model's boot ROM leaves it
+    /// The SGB and CGB values depend on how long the logo animation ran and are not
+    /// documented, so those start from 0x00
+    pub fn div(self) -> u8 {
+        match self {
+            Model::Dmg0 => 0x18,
+            Model::Dmg | Model::Mgb => 0xAB,
+            Model::Sgb | Model::Cgb => 0x00,
+        }
+    }
+    /// USAGE: model.io()
+    /// Returns every IO register the model's boot ROM leaves with a known value, DIV included,
+    /// as (address, value) pairs in address order
+    pub fn io(self) -> Vec<(u16, u8)> {
+        let (sc, nr52, stat, dma) = match self {
+            Model::Dmg0 => (0x7E, 0xF1, 0x81, 0xFF),
+            Model::Dmg | Model::Mgb => (0x7E, 0xF1, 0x85, 0xFF),
+            Model::Sgb => (0x7E, 0xF0, 0x85, 0xFF),
+            Model::Cgb => (0x7F, 0xF1, 0x85, 0x00),
+        };
+        let mut io = COMMON_IO.to_vec();
+        io.extend_from_slice(&[
+            (0xFF02, sc),
+            (DIV, self.div()),
+            (0xFF26, nr52),
+            (0xFF41, stat),
+            (0xFF44, 0x00), // LY
+            (0xFF46, dma),
+        ]);
+        if self == Model::Cgb {
+            // VBK and SVBK, whose unused bits read as 1, and RP
+            io.extend_from_slice(&[(0xFF4F, 0xFE), (0xFF56, 0x3E), (0xFF70, 0xF8)]);
+        }
+        io.sort();
+        io
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::CPU;
+
+    const MODELS: [Model; 5] = [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb];
+
+    // Checks the registers each boot ROM leaves behind, as printed in trace logs
+    #[test]
+    fn can_set_post_boot_registers() {
+        let traces = [
+            "A:01 F:00 B:FF C:13 D:00 E:C1 H:84 L:03 SP:FFFE PC:0100",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100",
+            "A:FF F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100",
+            "A:01 F:00 B:00 C:14 D:00 E:00 H:C0 L:60 SP:FFFE PC:0100",
+            "A:11 F:80 B:00 C:00 D:FF E:56 H:00 L:0D SP:FFFE PC:0100",
+        ];
+        for (model, trace) in MODELS.iter().zip(traces.iter()) {
+            let cpu = CPU::post_boot([0u8; 0x10000], *model, 0x0B);
+            assert_eq!(cpu.registers().to_string(), *trace);
+            assert!(!cpu.registers().ime);
+        }
+        // Only the DMG and MGB boot ROMs look at the header checksum
+        assert_eq!(Model::Dmg.registers(0x00).f, 0x80);
+        assert_eq!(Model::Mgb.registers(0x00).f, 0x80);
+        assert_eq!(Model::Sgb.registers(0x00), Model::Sgb.registers(0xFF));
+    }
+    // Checks that every model sets each IO register once, including DIV
+    #[test]
+    fn can_set_post_boot_io() {
+        for model in MODELS.iter() {
+            let io = model.io();
+            let mut addresses: Vec<u16> = io.iter().map(|&(address, _)| address).collect();
+            addresses.dedup();
+            assert_eq!(addresses.len(), io.len());
+            assert!(io.contains(&(DIV, model.div())));
+            assert!(io.contains(&(0xFF40, 0x91)));
+            assert!(addresses.iter().all(|&address| address >= 0xFF00));
+        }
+        assert_eq!(Model::Dmg.div(), 0xAB);
+        assert!(Model::Cgb.io().contains(&(0xFF70, 0xF8)));
+        assert!(!Model::Dmg.io().iter().any(|&(address, _)| address == 0xFF70));
+    }
+    // Checks that a cartridge starts running at 0x0100 with no boot ROM at all
+    #[test]
+    fn can_start_cartridge_without_boot_rom() {
+        let rom = include_bytes!("../tetris.gb");
+        let mut mem = [0u8; 0x10000];
+        mem[..rom.len()].copy_from_slice(rom);
+        for &(address, value) in Model::Dmg.io().iter() {
+            mem[address as usize] = value;
+        }
+        let mut cpu = CPU::post_boot(mem, Model::Dmg, rom[0x014D]);
+        assert_eq!(cpu.registers().f, 0xB0);
+        // Tetris starts with NOP ; JP $0150
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().pc, 0x0150);
+    }
+}
@@ -0,0 +1,999 @@
+use cpu::{Condition, Operand, R16, R8};
+use instruction::{Alu, Instruction, Shift};
+
+/// One row of the opcode table: everything there is to know about an opcode
+pub struct Opcode {
+    pub opcode: u8,
+    pub mnemonic: Mnemonic,
+    /// Operands in assembler order, destination first
+    pub operands: &'static [Arg],
+    /// Bytes taken up by the instruction, opcode and immediates included
+    pub length: u8,
+    /// M-cycles taken, or taken when a conditional branch is not taken
+    pub cycles: u8,
+    /// M-cycles taken by a conditional branch when it is taken
+    pub taken: Option<u8>,
+    /// Effect on Z, N, H and C in that order: '-' unchanged, '0' reset, '1' set,
+    /// or the flag's own letter when it depends on the operands
+    pub flags: &'static str,
+}
+
+/// Every mnemonic of the instruction set, plus the CB prefix and the illegal opcodes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mnemonic {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Ld,
+    Ldh,
+    Push,
+    Pop,
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+    Inc,
+    Dec,
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jp,
+    Jr,
+    Call,
+    Ret,
+    Reti,
+    Rst,
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+    Bit,
+    Res,
+    Set,
+    Prefix,
+    Illegal,
+}
+
+/// An operand as written in the opcode table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arg {
+    /// An 8-bit register
+    Reg(R8),
+    /// A 16-bit register
+    Pair(R16),
+    /// The byte at the address held in a 16-bit register, e.g. (HL)
+    Ind(R16),
+    /// (HL+), the byte at HL which is incremented after the access
+    HlInc,
+    /// (HL-), the byte at HL which is decremented after the access
+    HlDec,
+    /// (C), the byte at 0xFF00 + C
+    HighC,
+    /// An immediate byte
+    N8,
+    /// An immediate little-endian word
+    N16,
+    /// (a8), the byte at 0xFF00 plus an immediate byte
+    A8,
+    /// (a16), the byte at an immediate address
+    A16,
+    /// An immediate signed offset
+    E8,
+    /// SP plus an immediate signed offset
+    SpE8,
+    /// A branch condition
+    Cond(Condition),
+    /// A bit number for BIT, RES and SET
+    Bit(u8),
+    /// The call vector of RST
+    Vector(u8),
+}
+
+const A: Arg = Arg::Reg(R8::A);
+const B: Arg = Arg::Reg(R8::B);
+const C: Arg = Arg::Reg(R8::C);
+const D: Arg = Arg::Reg(R8::D);
+const E: Arg = Arg::Reg(R8::E);
+const H: Arg = Arg::Reg(R8::H);
+const L: Arg = Arg::Reg(R8::L);
+const AF: Arg = Arg::Pair(R16::AF);
+const BC: Arg = Arg::Pair(R16::BC);
+const DE: Arg = Arg::Pair(R16::DE);
+const HL: Arg = Arg::Pair(R16::HL);
+const SP: Arg = Arg::Pair(R16::SP);
+const M_BC: Arg = Arg::Ind(R16::BC);
+const M_DE: Arg = Arg::Ind(R16::DE);
+const M_HL: Arg = Arg::Ind(R16::HL);
+const M_HLI: Arg = Arg::HlInc;
+const M_HLD: Arg = Arg::HlDec;
+const M_C: Arg = Arg::HighC;
+const N8: Arg = Arg::N8;
+const N16: Arg = Arg::N16;
+const A8: Arg = Arg::A8;
+const A16: Arg = Arg::A16;
+const E8: Arg = Arg::E8;
+const SP_E8: Arg = Arg::SpE8;
+const IF_NZ: Arg = Arg::Cond(Condition::NZ);
+const IF_Z: Arg = Arg::Cond(Condition::Z);
+const IF_NC: Arg = Arg::Cond(Condition::NC);
+const IF_C: Arg = Arg::Cond(Condition::C);
+
+macro_rules! op {
+    ($opcode:expr, $mnemonic:ident, [$($arg:expr),*], $length:expr, $cycles:expr, $flags:expr) => {
+        Opcode {
+            opcode: $opcode,
+            mnemonic: Mnemonic::$mnemonic,
+            operands: &[$($arg),*],
+            length: $length,
+            cycles: $cycles,
+            taken: None,
+            flags: $flags,
+        }
+    };
+    (
+        $opcode:expr,
+        $mnemonic:ident,
+        [$($arg:expr),*],
+        $length:expr,
+        $cycles:expr,
+        $taken:expr,
+        $flags:expr
+    ) => {
+        Opcode {
+            opcode: $opcode,
+            mnemonic: Mnemonic::$mnemonic,
+            operands: &[$($arg),*],
+            length: $length,
+            cycles: $cycles,
+            taken: Some($taken),
+            flags: $flags,
+        }
+    };
+}
+
+/// Every base opcode, indexed by its first byte
+pub static OPCODES: [Opcode; 256] = [
+    op!(0x00, Nop, [], 1, 1, "----"),
+    op!(0x01, Ld, [BC, N16], 3, 3, "----"),
+    op!(0x02, Ld, [M_BC, A], 1, 2, "----"),
+    op!(0x03, Inc, [BC], 1, 2, "----"),
+    op!(0x04, Inc, [B], 1, 1, "Z0H-"),
+    op!(0x05, Dec, [B], 1, 1, "Z1H-"),
+    op!(0x06, Ld, [B, N8], 2, 2, "----"),
+    op!(0x07, Rlca, [], 1, 1, "000C"),
+    op!(0x08, Ld, [A16, SP], 3, 5, "----"),
+    op!(0x09, Add, [HL, BC], 1, 2, "-0HC"),
+    op!(0x0A, Ld, [A, M_BC], 1, 2, "----"),
+    op!(0x0B, Dec, [BC], 1, 2, "----"),
+    op!(0x0C, Inc, [C], 1, 1, "Z0H-"),
+    op!(0x0D, Dec, [C], 1, 1, "Z1H-"),
+    op!(0x0E, Ld, [C, N8], 2, 2, "----"),
+    op!(0x0F, Rrca, [], 1, 1, "000C"),
+    op!(0x10, Stop, [], 2, 2, "----"),
+    op!(0x11, Ld, [DE, N16], 3, 3, "----"),
+    op!(0x12, Ld, [M_DE, A], 1, 2, "----"),
+    op!(0x13, Inc, [DE], 1, 2, "----"),
+    op!(0x14, Inc, [D], 1, 1, "Z0H-"),
+    op!(0x15, Dec, [D], 1, 1, "Z1H-"),
+    op!(0x16, Ld, [D, N8], 2, 2, "----"),
+    op!(0x17, Rla, [], 1, 1, "000C"),
+    op!(0x18, Jr, [E8], 2, 3, "----"),
+    op!(0x19, Add, [HL, DE], 1, 2, "-0HC"),
+    op!(0x1A, Ld, [A, M_DE], 1, 2, "----"),
+    op!(0x1B, Dec, [DE], 1, 2, "----"),
+    op!(0x1C, Inc, [E], 1, 1, "Z0H-"),
+    op!(0x1D, Dec, [E], 1, 1, "Z1H-"),
+    op!(0x1E, Ld, [E, N8], 2, 2, "----"),
+    op!(0x1F, Rra, [], 1, 1, "000C"),
+    op!(0x20, Jr, [IF_NZ, E8], 2, 2, 3, "----"),
+    op!(0x21, Ld, [HL, N16], 3, 3, "----"),
+    op!(0x22, Ld, [M_HLI, A], 1, 2, "----"),
+    op!(0x23, Inc, [HL], 1, 2, "----"),
+    op!(0x24, Inc, [H], 1, 1, "Z0H-"),
+    op!(0x25, Dec, [H], 1, 1, "Z1H-"),
+    op!(0x26, Ld, [H, N8], 2, 2, "----"),
+    op!(0x27, Daa, [], 1, 1, "Z-0C"),
+    op!(0x28, Jr, [IF_Z, E8], 2, 2, 3, "----"),
+    op!(0x29, Add, [HL, HL], 1, 2, "-0HC"),
+    op!(0x2A, Ld, [A, M_HLI], 1, 2, "----"),
+    op!(0x2B, Dec, [HL], 1, 2, "----"),
+    op!(0x2C, Inc, [L], 1, 1, "Z0H-"),
+    op!(0x2D, Dec, [L], 1, 1, "Z1H-"),
+    op!(0x2E, Ld, [L, N8], 2, 2, "----"),
+    op!(0x2F, Cpl, [], 1, 1, "-11-"),
+    op!(0x30, Jr, [IF_NC, E8], 2, 2, 3, "----"),
+    op!(0x31, Ld, [SP, N16], 3, 3, "----"),
+    op!(0x32, Ld, [M_HLD, A], 1, 2, "----"),
+    op!(0x33, Inc, [SP], 1, 2, "----"),
+    op!(0x34, Inc, [M_HL], 1, 3, "Z0H-"),
+    op!(0x35, Dec, [M_HL], 1, 3, "Z1H-"),
+    op!(0x36, Ld, [M_HL, N8], 2, 3, "----"),
+    op!(0x37, Scf, [], 1, 1, "-001"),
+    op!(0x38, Jr, [IF_C, E8], 2, 2, 3, "----"),
+    op!(0x39, Add, [HL, SP], 1, 2, "-0HC"),
+    op!(0x3A, Ld, [A, M_HLD], 1, 2, "----"),
+    op!(0x3B, Dec, [SP], 1, 2, "----"),
+    op!(0x3C, Inc, [A], 1, 1, "Z0H-"),
+    op!(0x3D, Dec, [A], 1, 1, "Z1H-"),
+    op!(0x3E, Ld, [A, N8], 2, 2, "----"),
+    op!(0x3F, Ccf, [], 1, 1, "-00C"),
+    op!(0x40, Ld, [B, B], 1, 1, "----"),
+    op!(0x41, Ld, [B, C], 1, 1, "----"),
+    op!(0x42, Ld, [B, D], 1, 1, "----"),
+    op!(0x43, Ld, [B, E], 1, 1, "----"),
+    op!(0x44, Ld, [B, H], 1, 1, "----"),
+    op!(0x45, Ld, [B, L], 1, 1, "----"),
+    op!(0x46, Ld, [B, M_HL], 1, 2, "----"),
+    op!(0x47, Ld, [B, A], 1, 1, "----"),
+    op!(0x48, Ld, [C, B], 1, 1, "----"),
+    op!(0x49, Ld, [C, C], 1, 1, "----"),
+    op!(0x4A, Ld, [C, D], 1, 1, "----"),
+    op!(0x4B, Ld, [C, E], 1, 1, "----"),
+    op!(0x4C, Ld, [C, H], 1, 1, "----"),
+    op!(0x4D, Ld, [C, L], 1, 1, "----"),
+    op!(0x4E, Ld, [C, M_HL], 1, 2, "----"),
+    op!(0x4F, Ld, [C, A], 1, 1, "----"),
+    op!(0x50, Ld, [D, B], 1, 1, "----"),
+    op!(0x51, Ld, [D, C], 1, 1, "----"),
+    op!(0x52, Ld, [D, D], 1, 1, "----"),
+    op!(0x53, Ld, [D, E], 1, 1, "----"),
+    op!(0x54, Ld, [D, H], 1, 1, "----"),
+    op!(0x55, Ld, [D, L], 1, 1, "----"),
+    op!(0x56, Ld, [D, M_HL], 1, 2, "----"),
+    op!(0x57, Ld, [D, A], 1, 1, "----"),
+    op!(0x58, Ld, [E, B], 1, 1, "----"),
+    op!(0x59, Ld, [E, C], 1, 1, "----"),
+    op!(0x5A, Ld, [E, D], 1, 1, "----"),
+    op!(0x5B, Ld, [E, E], 1, 1, "----"),
+    op!(0x5C, Ld, [E, H], 1, 1, "----"),
+    op!(0x5D, Ld, [E, L], 1, 1, "----"),
+    op!(0x5E, Ld, [E, M_HL], 1, 2, "----"),
+    op!(0x5F, Ld, [E, A], 1, 1, "----"),
+    op!(0x60, Ld, [H, B], 1, 1, "----"),
+    op!(0x61, Ld, [H, C], 1, 1, "----"),
+    op!(0x62, Ld, [H, D], 1, 1, "----"),
+    op!(0x63, Ld, [H, E], 1, 1, "----"),
+    op!(0x64, Ld, [H, H], 1, 1, "----"),
+    op!(0x65, Ld, [H, L], 1, 1, "----"),
+    op!(0x66, Ld, [H, M_HL], 1, 2, "----"),
+    op!(0x67, Ld, [H, A], 1, 1, "----"),
+    op!(0x68, Ld, [L, B], 1, 1, "----"),
+    op!(0x69, Ld, [L, C], 1, 1, "----"),
+    op!(0x6A, Ld, [L, D], 1, 1, "----"),
+    op!(0x6B, Ld, [L, E], 1, 1, "----"),
+    op!(0x6C, Ld, [L, H], 1, 1, "----"),
+    op!(0x6D, Ld, [L, L], 1, 1, "----"),
+    op!(0x6E, Ld, [L, M_HL], 1, 2, "----"),
+    op!(0x6F, Ld, [L, A], 1, 1, "----"),
+    op!(0x70, Ld, [M_HL, B], 1, 2, "----"),
+    op!(0x71, Ld, [M_HL, C], 1, 2, "----"),
+    op!(0x72, Ld, [M_HL, D], 1, 2, "----"),
+    op!(0x73, Ld, [M_HL, E], 1, 2, "----"),
+    op!(0x74, Ld, [M_HL, H], 1, 2, "----"),
+    op!(0x75, Ld, [M_HL, L], 1, 2, "----"),
+    op!(0x76, Halt, [], 1, 1, "----"),
+    op!(0x77, Ld, [M_HL, A], 1, 2, "----"),
+    op!(0x78, Ld, [A, B], 1, 1, "----"),
+    op!(0x79, Ld, [A, C], 1, 1, "----"),
+    op!(0x7A, Ld, [A, D], 1, 1, "----"),
+    op!(0x7B, Ld, [A, E], 1, 1, "----"),
+    op!(0x7C, Ld, [A, H], 1, 1, "----"),
+    op!(0x7D, Ld, [A, L], 1, 1, "----"),
+    op!(0x7E, Ld, [A, M_HL], 1, 2, "----"),
+    op!(0x7F, Ld, [A, A], 1, 1, "----"),
+    op!(0x80, Add, [A, B], 1, 1, "Z0HC"),
+    op!(0x81, Add, [A, C], 1, 1, "Z0HC"),
+    op!(0x82, Add, [A, D], 1, 1, "Z0HC"),
+    op!(0x83, Add, [A, E], 1, 1, "Z0HC"),
+    op!(0x84, Add, [A, H], 1, 1, "Z0HC"),
+    op!(0x85, Add, [A, L], 1, 1, "Z0HC"),
+    op!(0x86, Add, [A, M_HL], 1, 2, "Z0HC"),
+    op!(0x87, Add, [A, A], 1, 1, "Z0HC"),
+    op!(0x88, Adc, [A, B], 1, 1, "Z0HC"),
+    op!(0x89, Adc, [A, C], 1, 1, "Z0HC"),
+    op!(0x8A, Adc, [A, D], 1, 1, "Z0HC"),
+    op!(0x8B, Adc, [A, E], 1, 1, "Z0HC"),
+    op!(0x8C, Adc, [A, H], 1, 1, "Z0HC"),
+    op!(0x8D, Adc, [A, L], 1, 1, "Z0HC"),
+    op!(0x8E, Adc, [A, M_HL], 1, 2, "Z0HC"),
+    op!(0x8F, Adc, [A, A], 1, 1, "Z0HC"),
+    op!(0x90, Sub, [B], 1, 1, "Z1HC"),
+    op!(0x91, Sub, [C], 1, 1, "Z1HC"),
+    op!(0x92, Sub, [D], 1, 1, "Z1HC"),
+    op!(0x93, Sub, [E], 1, 1, "Z1HC"),
+    op!(0x94, Sub, [H], 1, 1, "Z1HC"),
+    op!(0x95, Sub, [L], 1, 1, "Z1HC"),
+    op!(0x96, Sub, [M_HL], 1, 2, "Z1HC"),
+    op!(0x97, Sub, [A], 1, 1, "Z1HC"),
+    op!(0x98, Sbc, [A, B], 1, 1, "Z1HC"),
+    op!(0x99, Sbc, [A, C], 1, 1, "Z1HC"),
+    op!(0x9A, Sbc, [A, D], 1, 1, "Z1HC"),
+    op!(0x9B, Sbc, [A, E], 1, 1, "Z1HC"),
+    op!(0x9C, Sbc, [A, H], 1, 1, "Z1HC"),
+    op!(0x9D, Sbc, [A, L], 1, 1, "Z1HC"),
+    op!(0x9E, Sbc, [A, M_HL], 1, 2, "Z1HC"),
+    op!(0x9F, Sbc, [A, A], 1, 1, "Z1HC"),
+    op!(0xA0, And, [B], 1, 1, "Z010"),
+    op!(0xA1, And, [C], 1, 1, "Z010"),
+    op!(0xA2, And, [D], 1, 1, "Z010"),
+    op!(0xA3, And, [E], 1, 1, "Z010"),
+    op!(0xA4, And, [H], 1, 1, "Z010"),
+    op!(0xA5, And, [L], 1, 1, "Z010"),
+    op!(0xA6, And, [M_HL], 1, 2, "Z010"),
+    op!(0xA7, And, [A], 1, 1, "Z010"),
+    op!(0xA8, Xor, [B], 1, 1, "Z000"),
+    op!(0xA9, Xor, [C], 1, 1, "Z000"),
+    op!(0xAA, Xor, [D], 1, 1, "Z000"),
+    op!(0xAB, Xor, [E], 1, 1, "Z000"),
+    op!(0xAC, Xor, [H], 1, 1, "Z000"),
+    op!(0xAD, Xor, [L], 1, 1, "Z000"),
+    op!(0xAE, Xor, [M_HL], 1, 2, "Z000"),
+    op!(0xAF, Xor, [A], 1, 1, "Z000"),
+    op!(0xB0, Or, [B], 1, 1, "Z000"),
+    op!(0xB1, Or, [C], 1, 1, "Z000"),
+    op!(0xB2, Or, [D], 1, 1, "Z000"),
+    op!(0xB3, Or, [E], 1, 1, "Z000"),
+    op!(0xB4, Or, [H], 1, 1, "Z000"),
+    op!(0xB5, Or, [L], 1, 1, "Z000"),
+    op!(0xB6, Or, [M_HL], 1, 2, "Z000"),
+    op!(0xB7, Or, [A], 1, 1, "Z000"),
+    op!(0xB8, Cp, [B], 1, 1, "Z1HC"),
+    op!(0xB9, Cp, [C], 1, 1, "Z1HC"),
+    op!(0xBA, Cp, [D], 1, 1, "Z1HC"),
+    op!(0xBB, Cp, [E], 1, 1, "Z1HC"),
+    op!(0xBC, Cp, [H], 1, 1, "Z1HC"),
+    op!(0xBD, Cp, [L], 1, 1, "Z1HC"),
+    op!(0xBE, Cp, [M_HL], 1, 2, "Z1HC"),
+    op!(0xBF, Cp, [A], 1, 1, "Z1HC"),
+    op!(0xC0, Ret, [IF_NZ], 1, 2, 5, "----"),
+    op!(0xC1, Pop, [BC], 1, 3, "----"),
+    op!(0xC2, Jp, [IF_NZ, N16], 3, 3, 4, "----"),
+    op!(0xC3, Jp, [N16], 3, 4, "----"),
+    op!(0xC4, Call, [IF_NZ, N16], 3, 3, 6, "----"),
+    op!(0xC5, Push, [BC], 1, 4, "----"),
+    op!(0xC6, Add, [A, N8], 2, 2, "Z0HC"),
+    op!(0xC7, Rst, [Arg::Vector(0x00)], 1, 4, "----"),
+    op!(0xC8, Ret, [IF_Z], 1, 2, 5, "----"),
+    op!(0xC9, Ret, [], 1, 4, "----"),
+    op!(0xCA, Jp, [IF_Z, N16], 3, 3, 4, "----"),
+    op!(0xCB, Prefix, [], 2, 1, "----"),
+    op!(0xCC, Call, [IF_Z, N16], 3, 3, 6, "----"),
+    op!(0xCD, Call, [N16], 3, 6, "----"),
+    op!(0xCE, Adc, [A, N8], 2, 2, "Z0HC"),
+    op!(0xCF, Rst, [Arg::Vector(0x08)], 1, 4, "----"),
+    op!(0xD0, Ret, [IF_NC], 1, 2, 5, "----"),
+    op!(0xD1, Pop, [DE], 1, 3, "----"),
+    op!(0xD2, Jp, [IF_NC, N16], 3, 3, 4, "----"),
+    op!(0xD3, Illegal, [], 1, 1, "----"),
+    op!(0xD4, Call, [IF_NC, N16], 3, 3, 6, "----"),
+    op!(0xD5, Push, [DE], 1, 4, "----"),
+    op!(0xD6, Sub, [N8], 2, 2, "Z1HC"),
+    op!(0xD7, Rst, [Arg::Vector(0x10)], 1, 4, "----"),
+    op!(0xD8, Ret, [IF_C], 1, 2, 5, "----"),
+    op!(0xD9, Reti, [], 1, 4, "----"),
+    op!(0xDA, Jp, [IF_C, N16], 3, 3, 4, "----"),
+    op!(0xDB, Illegal, [], 1, 1, "----"),
+    op!(0xDC, Call, [IF_C, N16], 3, 3, 6, "----"),
+    op!(0xDD, Illegal, [], 1, 1, "----"),
+    op!(0xDE, Sbc, [A, N8], 2, 2, "Z1HC"),
+    op!(0xDF, Rst, [Arg::Vector(0x18)], 1, 4, "----"),
+    op!(0xE0, Ldh, [A8, A], 2, 3, "----"),
+    op!(0xE1, Pop, [HL], 1, 3, "----"),
+    op!(0xE2, Ld, [M_C, A], 1, 2, "----"),
+    op!(0xE3, Illegal, [], 1, 1, "----"),
+    op!(0xE4, Illegal, [], 1, 1, "----"),
+    op!(0xE5, Push, [HL], 1, 4, "----"),
+    op!(0xE6, And, [N8], 2, 2, "Z010"),
+    op!(0xE7, Rst, [Arg::Vector(0x20)], 1, 4, "----"),
+    op!(0xE8, Add, [SP, E8], 2, 4, "00HC"),
+    op!(0xE9, Jp, [HL], 1, 1, "----"),
+    op!(0xEA, Ld, [A16, A], 3, 4, "----"),
+    op!(0xEB, Illegal, [], 1, 1, "----"),
+    op!(0xEC, Illegal, [], 1, 1, "----"),
+    op!(0xED, Illegal, [], 1, 1, "----"),
+    op!(0xEE, Xor, [N8], 2, 2, "Z000"),
+    op!(0xEF, Rst, [Arg::Vector(0x28)], 1, 4, "----"),
+    op!(0xF0, Ldh, [A, A8], 2, 3, "----"),
+    op!(0xF1, Pop, [AF], 1, 3, "ZNHC"),
+    op!(0xF2, Ld, [A, M_C], 1, 2, "----"),
+    op!(0xF3, Di, [], 1, 1, "----"),
+    op!(0xF4, Illegal, [], 1, 1, "----"),
+    op!(0xF5, Push, [AF], 1, 4, "----"),
+    op!(0xF6, Or, [N8], 2, 2, "Z000"),
+    op!(0xF7, Rst, [Arg::Vector(0x30)], 1, 4, "----"),
+    op!(0xF8, Ld, [HL, SP_E8], 2, 3, "00HC"),
+    op!(0xF9, Ld, [SP, HL], 1, 2, "----"),
+    op!(0xFA, Ld, [A, A16], 3, 4, "----"),
+    op!(0xFB, Ei, [], 1, 1, "----"),
+    op!(0xFC, Illegal, [], 1, 1, "----"),
+    op!(0xFD, Illegal, [], 1, 1, "----"),
+    op!(0xFE, Cp, [N8], 2, 2, "Z1HC"),
+    op!(0xFF, Rst, [Arg::Vector(0x38)], 1, 4, "----"),
+];
+
+/// Every CB-prefixed opcode, indexed by the byte after the prefix
+/// Lengths and cycles include the prefix byte
+pub static CB_OPCODES: [Opcode; 256] = [
+    op!(0x00, Rlc, [B], 2, 2, "Z00C"),
+    op!(0x01, Rlc, [C], 2, 2, "Z00C"),
+    op!(0x02, Rlc, [D], 2, 2, "Z00C"),
+    op!(0x03, Rlc, [E], 2, 2, "Z00C"),
+    op!(0x04, Rlc, [H], 2, 2, "Z00C"),
+    op!(0x05, Rlc, [L], 2, 2, "Z00C"),
+    op!(0x06, Rlc, [M_HL], 2, 4, "Z00C"),
+    op!(0x07, Rlc, [A], 2, 2, "Z00C"),
+    op!(0x08, Rrc, [B], 2, 2, "Z00C"),
+    op!(0x09, Rrc, [C], 2, 2, "Z00C"),
+    op!(0x0A, Rrc, [D], 2, 2, "Z00C"),
+    op!(0x0B, Rrc, [E], 2, 2, "Z00C"),
+    op!(0x0C, Rrc, [H], 2, 2, "Z00C"),
+    op!(0x0D, Rrc, [L], 2, 2, "Z00C"),
+    op!(0x0E, Rrc, [M_HL], 2, 4, "Z00C"),
+    op!(0x0F, Rrc, [A], 2, 2, "Z00C"),
+    op!(0x10, Rl, [B], 2, 2, "Z00C"),
+    op!(0x11, Rl, [C], 2, 2, "Z00C"),
+    op!(0x12, Rl, [D], 2, 2, "Z00C"),
+    op!(0x13, Rl, [E], 2, 2, "Z00C"),
+    op!(0x14, Rl, [H], 2, 2, "Z00C"),
+    op!(0x15, Rl, [L], 2, 2, "Z00C"),
+    op!(0x16, Rl, [M_HL], 2, 4, "Z00C"),
+    op!(0x17, Rl, [A], 2, 2, "Z00C"),
+    op!(0x18, Rr, [B], 2, 2, "Z00C"),
+    op!(0x19, Rr, [C], 2, 2, "Z00C"),
+    op!(0x1A, Rr, [D], 2, 2, "Z00C"),
+    op!(0x1B, Rr, [E], 2, 2, "Z00C"),
+    op!(0x1C, Rr, [H], 2, 2, "Z00C"),
+    op!(0x1D, Rr, [L], 2, 2, "Z00C"),
+    op!(0x1E, Rr, [M_HL], 2, 4, "Z00C"),
+    op!(0x1F, Rr, [A], 2, 2, "Z00C"),
+    op!(0x20, Sla, [B], 2, 2, "Z00C"),
+    op!(0x21, Sla, [C], 2, 2, "Z00C"),
+    op!(0x22, Sla, [D], 2, 2, "Z00C"),
+    op!(0x23, Sla, [E], 2, 2, "Z00C"),
+    op!(0x24, Sla, [H], 2, 2, "Z00C"),
+    op!(0x25, Sla, [L], 2, 2, "Z00C"),
+    op!(0x26, Sla, [M_HL], 2, 4, "Z00C"),
+    op!(0x27, Sla, [A], 2, 2, "Z00C"),
+    op!(0x28, Sra, [B], 2, 2, "Z00C"),
+    op!(0x29, Sra, [C], 2, 2, "Z00C"),
+    op!(0x2A, Sra, [D], 2, 2, "Z00C"),
+    op!(0x2B, Sra, [E], 2, 2, "Z00C"),
+    op!(0x2C, Sra, [H], 2, 2, "Z00C"),
+    op!(0x2D, Sra, [L], 2, 2, "Z00C"),
+    op!(0x2E, Sra, [M_HL], 2, 4, "Z00C"),
+    op!(0x2F, Sra, [A], 2, 2, "Z00C"),
+    op!(0x30, Swap, [B], 2, 2, "Z000"),
+    op!(0x31, Swap, [C], 2, 2, "Z000"),
+    op!(0x32, Swap, [D], 2, 2, "Z000"),
+    op!(0x33, Swap, [E], 2, 2, "Z000"),
+    op!(0x34, Swap, [H], 2, 2, "Z000"),
+    op!(0x35, Swap, [L], 2, 2, "Z000"),
+    op!(0x36, Swap, [M_HL], 2, 4, "Z000"),
+    op!(0x37, Swap, [A], 2, 2, "Z000"),
+    op!(0x38, Srl, [B], 2, 2, "Z00C"),
+    op!(0x39, Srl, [C], 2, 2, "Z00C"),
+    op!(0x3A, Srl, [D], 2, 2, "Z00C"),
+    op!(0x3B, Srl, [E], 2, 2, "Z00C"),
+    op!(0x3C, Srl, [H], 2, 2, "Z00C"),
+    op!(0x3D, Srl, [L], 2, 2, "Z00C"),
+    op!(0x3E, Srl, [M_HL], 2, 4, "Z00C"),
+    op!(0x3F, Srl, [A], 2, 2, "Z00C"),
+    op!(0x40, Bit, [Arg::Bit(0), B], 2, 2, "Z01-"),
+    op!(0x41, Bit, [Arg::Bit(0), C], 2, 2, "Z01-"),
+    op!(0x42, Bit, [Arg::Bit(0), D], 2, 2, "Z01-"),
+    op!(0x43, Bit, [Arg::Bit(0), E], 2, 2, "Z01-"),
+    op!(0x44, Bit, [Arg::Bit(0), H], 2, 2, "Z01-"),
+    op!(0x45, Bit, [Arg::Bit(0), L], 2, 2, "Z01-"),
+    op!(0x46, Bit, [Arg::Bit(0), M_HL], 2, 3, "Z01-"),
+    op!(0x47, Bit, [Arg::Bit(0), A], 2, 2, "Z01-"),
+    op!(0x48, Bit, [Arg::Bit(1), B], 2, 2, "Z01-"),
+    op!(0x49, Bit, [Arg::Bit(1), C], 2, 2, "Z01-"),
+    op!(0x4A, Bit, [Arg::Bit(1), D], 2, 2, "Z01-"),
+    op!(0x4B, Bit, [Arg::Bit(1), E], 2, 2, "Z01-"),
+    op!(0x4C, Bit, [Arg::Bit(1), H], 2, 2, "Z01-"),
+    op!(0x4D, Bit, [Arg::Bit(1), L], 2, 2, "Z01-"),
+    op!(0x4E, Bit, [Arg::Bit(1), M_HL], 2, 3, "Z01-"),
+    op!(0x4F, Bit, [Arg::Bit(1), A], 2, 2, "Z01-"),
+    op!(0x50, Bit, [Arg::Bit(2), B], 2, 2, "Z01-"),
+    op!(0x51, Bit, [Arg::Bit(2), C], 2, 2, "Z01-"),
+    op!(0x52, Bit, [Arg::Bit(2), D], 2, 2, "Z01-"),
+    op!(0x53, Bit, [Arg::Bit(2), E], 2, 2, "Z01-"),
+    op!(0x54, Bit, [Arg::Bit(2), H], 2, 2, "Z01-"),
+    op!(0x55, Bit, [Arg::Bit(2), L], 2, 2, "Z01-"),
+    op!(0x56, Bit, [Arg::Bit(2), M_HL], 2, 3, "Z01-"),
+    op!(0x57, Bit, [Arg::Bit(2), A], 2, 2, "Z01-"),
+    op!(0x58, Bit, [Arg::Bit(3), B], 2, 2, "Z01-"),
+    op!(0x59, Bit, [Arg::Bit(3), C], 2, 2, "Z01-"),
+    op!(0x5A, Bit, [Arg::Bit(3), D], 2, 2, "Z01-"),
+    op!(0x5B, Bit, [Arg::Bit(3), E], 2, 2, "Z01-"),
+    op!(0x5C, Bit, [Arg::Bit(3), H], 2, 2, "Z01-"),
+    op!(0x5D, Bit, [Arg::Bit(3), L], 2, 2, "Z01-"),
+    op!(0x5E, Bit, [Arg::Bit(3), M_HL], 2, 3, "Z01-"),
+    op!(0x5F, Bit, [Arg::Bit(3), A], 2, 2, "Z01-"),
+    op!(0x60, Bit, [Arg::Bit(4), B], 2, 2, "Z01-"),
+    op!(0x61, Bit, [Arg::Bit(4), C], 2, 2, "Z01-"),
+    op!(0x62, Bit, [Arg::Bit(4), D], 2, 2, "Z01-"),
+    op!(0x63, Bit, [Arg::Bit(4), E], 2, 2, "Z01-"),
+    op!(0x64, Bit, [Arg::Bit(4), H], 2, 2, "Z01-"),
+    op!(0x65, Bit, [Arg::Bit(4), L], 2, 2, "Z01-"),
+    op!(0x66, Bit, [Arg::Bit(4), M_HL], 2, 3, "Z01-"),
+    op!(0x67, Bit, [Arg::Bit(4), A], 2, 2, "Z01-"),
+    op!(0x68, Bit, [Arg::Bit(5), B], 2, 2, "Z01-"),
+    op!(0x69, Bit, [Arg::Bit(5), C], 2, 2, "Z01-"),
+    op!(0x6A, Bit, [Arg::Bit(5), D], 2, 2, "Z01-"),
+    op!(0x6B, Bit, [Arg::Bit(5), E], 2, 2, "Z01-"),
+    op!(0x6C, Bit, [Arg::Bit(5), H], 2, 2, "Z01-"),
+    op!(0x6D, Bit, [Arg::Bit(5), L], 2, 2, "Z01-"),
+    op!(0x6E, Bit, [Arg::Bit(5), M_HL], 2, 3, "Z01-"),
+    op!(0x6F, Bit, [Arg::Bit(5), A], 2, 2, "Z01-"),
+    op!(0x70, Bit, [Arg::Bit(6), B], 2, 2, "Z01-"),
+    op!(0x71, Bit, [Arg::Bit(6), C], 2, 2, "Z01-"),
+    op!(0x72, Bit, [Arg::Bit(6), D], 2, 2, "Z01-"),
+    op!(0x73, Bit, [Arg::Bit(6), E], 2, 2, "Z01-"),
+    op!(0x74, Bit, [Arg::Bit(6), H], 2, 2, "Z01-"),
+    op!(0x75, Bit, [Arg::Bit(6), L], 2, 2, "Z01-"),
+    op!(0x76, Bit, [Arg::Bit(6), M_HL], 2, 3, "Z01-"),
+    op!(0x77, Bit, [Arg::Bit(6), A], 2, 2, "Z01-"),
+    op!(0x78, Bit, [Arg::Bit(7), B], 2, 2, "Z01-"),
+    op!(0x79, Bit, [Arg::Bit(7), C], 2, 2, "Z01-"),
+    op!(0x7A, Bit, [Arg::Bit(7), D], 2, 2, "Z01-"),
+    op!(0x7B, Bit, [Arg::Bit(7), E], 2, 2, "Z01-"),
+    op!(0x7C, Bit, [Arg::Bit(7), H], 2, 2, "Z01-"),
+    op!(0x7D, Bit, [Arg::Bit(7), L], 2, 2, "Z01-"),
+    op!(0x7E, Bit, [Arg::Bit(7), M_HL], 2, 3, "Z01-"),
+    op!(0x7F, Bit, [Arg::Bit(7), A], 2, 2, "Z01-"),
+    op!(0x80, Res, [Arg::Bit(0), B], 2, 2, "----"),
+    op!(0x81, Res, [Arg::Bit(0), C], 2, 2, "----"),
+    op!(0x82, Res, [Arg::Bit(0), D], 2, 2, "----"),
+    op!(0x83, Res, [Arg::Bit(0), E], 2, 2, "----"),
+    op!(0x84, Res, [Arg::Bit(0), H], 2, 2, "----"),
+    op!(0x85, Res, [Arg::Bit(0), L], 2, 2, "----"),
+    op!(0x86, Res, [Arg::Bit(0), M_HL], 2, 4, "----"),
+    op!(0x87, Res, [Arg::Bit(0), A], 2, 2, "----"),
+    op!(0x88, Res, [Arg::Bit(1), B], 2, 2, "----"),
+    op!(0x89, Res, [Arg::Bit(1), C], 2, 2, "----"),
+    op!(0x8A, Res, [Arg::Bit(1), D], 2, 2, "----"),
+    op!(0x8B, Res, [Arg::Bit(1), E], 2, 2, "----"),
+    op!(0x8C, Res, [Arg::Bit(1), H], 2, 2, "----"),
+    op!(0x8D, Res, [Arg::Bit(1), L], 2, 2, "----"),
+    op!(0x8E, Res, [Arg::Bit(1), M_HL], 2, 4, "----"),
+    op!(0x8F, Res, [Arg::Bit(1), A], 2, 2, "----"),
+    op!(0x90, Res, [Arg::Bit(2), B], 2, 2, "----"),
+    op!(0x91, Res, [Arg::Bit(2), C], 2, 2, "----"),
+    op!(0x92, Res, [Arg::Bit(2), D], 2, 2, "----"),
+    op!(0x93, Res, [Arg::Bit(2), E], 2, 2, "----"),
+    op!(0x94, Res, [Arg::Bit(2), H], 2, 2, "----"),
+    op!(0x95, Res, [Arg::Bit(2), L], 2, 2, "----"),
+    op!(0x96, Res, [Arg::Bit(2), M_HL], 2, 4, "----"),
+    op!(0x97, Res, [Arg::Bit(2), A], 2, 2, "----"),
+    op!(0x98, Res, [Arg::Bit(3), B], 2, 2, "----"),
+    op!(0x99, Res, [Arg::Bit(3), C], 2, 2, "----"),
+    op!(0x9A, Res, [Arg::Bit(3), D], 2, 2, "----"),
+    op!(0x9B, Res, [Arg::Bit(3), E], 2, 2, "----"),
+    op!(0x9C, Res, [Arg::Bit(3), H], 2, 2, "----"),
+    op!(0x9D, Res, [Arg::Bit(3), L], 2, 2, "----"),
+    op!(0x9E, Res, [Arg::Bit(3), M_HL], 2, 4, "----"),
+    op!(0x9F, Res, [Arg::Bit(3), A], 2, 2, "----"),
+    op!(0xA0, Res, [Arg::Bit(4), B], 2, 2, "----"),
+    op!(0xA1, Res, [Arg::Bit(4), C], 2, 2, "----"),
+    op!(0xA2, Res, [Arg::Bit(4), D], 2, 2, "----"),
+    op!(0xA3, Res, [Arg::Bit(4), E], 2, 2, "----"),
+    op!(0xA4, Res, [Arg::Bit(4), H], 2, 2, "----"),
+    op!(0xA5, Res, [Arg::Bit(4), L], 2, 2, "----"),
+    op!(0xA6, Res, [Arg::Bit(4), M_HL], 2, 4, "----"),
+    op!(0xA7, Res, [Arg::Bit(4), A], 2, 2, "----"),
+    op!(0xA8, Res, [Arg::Bit(5), B], 2, 2, "----"),
+    op!(0xA9, Res, [Arg::Bit(5), C], 2, 2, "----"),
+    op!(0xAA, Res, [Arg::Bit(5), D], 2, 2, "----"),
+    op!(0xAB, Res, [Arg::Bit(5), E], 2, 2, "----"),
+    op!(0xAC, Res, [Arg::Bit(5), H], 2, 2, "----"),
+    op!(0xAD, Res, [Arg::Bit(5), L], 2, 2, "----"),
+    op!(0xAE, Res, [Arg::Bit(5), M_HL], 2, 4, "----"),
+    op!(0xAF, Res, [Arg::Bit(5), A], 2, 2, "----"),
+    op!(0xB0, Res, [Arg::Bit(6), B], 2, 2, "----"),
+    op!(0xB1, Res, [Arg::Bit(6), C], 2, 2, "----"),
+    op!(0xB2, Res, [Arg::Bit(6), D], 2, 2, "----"),
+    op!(0xB3, Res, [Arg::Bit(6), E], 2, 2, "----"),
+    op!(0xB4, Res, [Arg::Bit(6), H], 2, 2, "----"),
+    op!(0xB5, Res, [Arg::Bit(6), L], 2, 2, "----"),
+    op!(0xB6, Res, [Arg::Bit(6), M_HL], 2, 4, "----"),
+    op!(0xB7, Res, [Arg::Bit(6), A], 2, 2, "----"),
+    op!(0xB8, Res, [Arg::Bit(7), B], 2, 2, "----"),
+    op!(0xB9, Res, [Arg::Bit(7), C], 2, 2, "----"),
+    op!(0xBA, Res, [Arg::Bit(7), D], 2, 2, "----"),
+    op!(0xBB, Res, [Arg::Bit(7), E], 2, 2, "----"),
+    op!(0xBC, Res, [Arg::Bit(7), H], 2, 2, "----"),
+    op!(0xBD, Res, [Arg::Bit(7), L], 2, 2, "----"),
+    op!(0xBE, Res, [Arg::Bit(7), M_HL], 2, 4, "----"),
+    op!(0xBF, Res, [Arg::Bit(7), A], 2, 2, "----"),
+    op!(0xC0, Set, [Arg::Bit(0), B], 2, 2, "----"),
+    op!(0xC1, Set, [Arg::Bit(0), C], 2, 2, "----"),
+    op!(0xC2, Set, [Arg::Bit(0), D], 2, 2, "----"),
+    op!(0xC3, Set, [Arg::Bit(0), E], 2, 2, "----"),
+    op!(0xC4, Set, [Arg::Bit(0), H], 2, 2, "----"),
+    op!(0xC5, Set, [Arg::Bit(0), L], 2, 2, "----"),
+    op!(0xC6, Set, [Arg::Bit(0), M_HL], 2, 4, "----"),
+    op!(0xC7, Set, [Arg::Bit(0), A], 2, 2, "----"),
+    op!(0xC8, Set, [Arg::Bit(1), B], 2, 2, "----"),
+    op!(0xC9, Set, [Arg::Bit(1), C], 2, 2, "----"),
+    op!(0xCA, Set, [Arg::Bit(1), D], 2, 2, "----"),
+    op!(0xCB, Set, [Arg::Bit(1), E], 2, 2, "----"),
+    op!(0xCC, Set, [Arg::Bit(1), H], 2, 2, "----"),
+    op!(0xCD, Set, [Arg::Bit(1), L], 2, 2, "----"),
+    op!(0xCE, Set, [Arg::Bit(1), M_HL], 2, 4, "----"),
+    op!(0xCF, Set, [Arg::Bit(1), A], 2, 2, "----"),
+    op!(0xD0, Set, [Arg::Bit(2), B], 2, 2, "----"),
+    op!(0xD1, Set, [Arg::Bit(2), C], 2, 2, "----"),
+    op!(0xD2, Set, [Arg::Bit(2), D], 2, 2, "----"),
+    op!(0xD3, Set, [Arg::Bit(2), E], 2, 2, "----"),
+    op!(0xD4, Set, [Arg::Bit(2), H], 2, 2, "----"),
+    op!(0xD5, Set, [Arg::Bit(2), L], 2, 2, "----"),
+    op!(0xD6, Set, [Arg::Bit(2), M_HL], 2, 4, "----"),
+    op!(0xD7, Set, [Arg::Bit(2), A], 2, 2, "----"),
+    op!(0xD8, Set, [Arg::Bit(3), B], 2, 2, "----"),
+    op!(0xD9, Set, [Arg::Bit(3), C], 2, 2, "----"),
+    op!(0xDA, Set, [Arg::Bit(3), D], 2, 2, "----"),
+    op!(0xDB, Set, [Arg::Bit(3), E], 2, 2, "----"),
+    op!(0xDC, Set, [Arg::Bit(3), H], 2, 2, "----"),
+    op!(0xDD, Set, [Arg::Bit(3), L], 2, 2, "----"),
+    op!(0xDE, Set, [Arg::Bit(3), M_HL], 2, 4, "----"),
+    op!(0xDF, Set, [Arg::Bit(3), A], 2, 2, "----"),
+    op!(0xE0, Set, [Arg::Bit(4), B], 2, 2, "----"),
+    op!(0xE1, Set, [Arg::Bit(4), C], 2, 2, "----"),
+    op!(0xE2, Set, [Arg::Bit(4), D], 2, 2, "----"),
+    op!(0xE3, Set, [Arg::Bit(4), E], 2, 2, "----"),
+    op!(0xE4, Set, [Arg::Bit(4), H], 2, 2, "----"),
+    op!(0xE5, Set, [Arg::Bit(4), L], 2, 2, "----"),
+    op!(0xE6, Set, [Arg::Bit(4), M_HL], 2, 4, "----"),
+    op!(0xE7, Set, [Arg::Bit(4), A], 2, 2, "----"),
+    op!(0xE8, Set, [Arg::Bit(5), B], 2, 2, "----"),
+    op!(0xE9, Set, [Arg::Bit(5), C], 2, 2, "----"),
+    op!(0xEA, Set, [Arg::Bit(5), D], 2, 2, "----"),
+    op!(0xEB, Set, [Arg::Bit(5), E], 2, 2, "----"),
+    op!(0xEC, Set, [Arg::Bit(5), H], 2, 2, "----"),
+    op!(0xED, Set, [Arg::Bit(5), L], 2, 2, "----"),
+    op!(0xEE, Set, [Arg::Bit(5), M_HL], 2, 4, "----"),
+    op!(0xEF, Set, [Arg::Bit(5), A], 2, 2, "----"),
+    op!(0xF0, Set, [Arg::Bit(6), B], 2, 2, "----"),
+    op!(0xF1, Set, [Arg::Bit(6), C], 2, 2, "----"),
+    op!(0xF2, Set, [Arg::Bit(6), D], 2, 2, "----"),
+    op!(0xF3, Set, [Arg::Bit(6), E], 2, 2, "----"),
+    op!(0xF4, Set, [Arg::Bit(6), H], 2, 2, "----"),
+    op!(0xF5, Set, [Arg::Bit(6), L], 2, 2, "----"),
+    op!(0xF6, Set, [Arg::Bit(6), M_HL], 2, 4, "----"),
+    op!(0xF7, Set, [Arg::Bit(6), A], 2, 2, "----"),
+    op!(0xF8, Set, [Arg::Bit(7), B], 2, 2, "----"),
+    op!(0xF9, Set, [Arg::Bit(7), C], 2, 2, "----"),
+    op!(0xFA, Set, [Arg::Bit(7), D], 2, 2, "----"),
+    op!(0xFB, Set, [Arg::Bit(7), E], 2, 2, "----"),
+    op!(0xFC, Set, [Arg::Bit(7), H], 2, 2, "----"),
+    op!(0xFD, Set, [Arg::Bit(7), L], 2, 2, "----"),
+    op!(0xFE, Set, [Arg::Bit(7), M_HL], 2, 4, "----"),
+    op!(0xFF, Set, [Arg::Bit(7), A], 2, 2, "----"),
+];
+
+impl Opcode {
+    /// USAGE: Opcode::lookup(BYTES) where BYTES starts with an opcode
+    /// Returns the table row of the instruction at the start of BYTES, looking through the
+    /// CB prefix, or None if BYTES is too short to hold the whole instruction
+    pub fn lookup(bytes: &[u8]) -> Option<&'static Opcode> {
+        let row = &OPCODES[*bytes.first()? as usize];
+        if bytes.len() < row.length as usize {
+            None
+        } else if row.mnemonic == Mnemonic::Prefix {
+            Some(&CB_OPCODES[bytes[1] as usize])
+        } else {
+            Some(row)
+        }
+    }
+    /// USAGE: row.instruction(BYTES) where BYTES holds the whole instruction of this row
+    /// Builds the typed instruction the CPU executes, reading immediates out of BYTES
+    pub fn instruction(&self, bytes: &[u8]) -> Instruction {
+        let (n, nn) = immediates(bytes);
+        let operand = |arg: Arg| match arg {
+            Arg::Reg(register) => Operand::Reg(register),
+            Arg::Ind(register) => Operand::Ind(register),
+            Arg::HlInc => Operand::HlInc,
+            Arg::HlDec => Operand::HlDec,
+            Arg::HighC => Operand::HighC,
+            Arg::N8 => Operand::Reg(R8::CONST(n)),
+            Arg::A8 => Operand::High(n),
+            Arg::A16 => Operand::Abs(nn),
+            _ => unreachable!(),
+        };
+        let (e, always) = (n as i8, Condition::Always);
+        match (self.mnemonic, self.operands) {
+            (Mnemonic::Nop, _) => Instruction::Nop,
+            (Mnemonic::Stop, _) => Instruction::Stop,
+            (Mnemonic::Halt, _) => Instruction::Halt,
+            (Mnemonic::Di, _) => Instruction::Di,
+            (Mnemonic::Ei, _) => Instruction::Ei,
+            (Mnemonic::Ld, &[Arg::Pair(register), Arg::N16]) => Instruction::Ld16(register, nn),
+            (Mnemonic::Ld, &[Arg::A16, Arg::Pair(_)]) => Instruction::LdNnSp(nn),
+            (Mnemonic::Ld, &[Arg::Pair(_), Arg::Pair(_)]) => Instruction::LdSpHl,
+            (Mnemonic::Ld, &[Arg::Pair(_), Arg::SpE8]) => Instruction::LdHlSp(e),
+            (Mnemonic::Ld, &[to, from]) | (Mnemonic::Ldh, &[to, from]) => {
+                Instruction::Ld(operand(to), operand(from))
+            }
+            (Mnemonic::Push, &[Arg::Pair(register)]) => Instruction::Push(register),
+            (Mnemonic::Pop, &[Arg::Pair(register)]) => Instruction::Pop(register),
+            (Mnemonic::Add, &[Arg::Pair(_), Arg::Pair(register)]) => Instruction::AddHl(register),
+            (Mnemonic::Add, &[Arg::Pair(_), Arg::E8]) => Instruction::AddSp(e),
+            (Mnemonic::Inc, &[Arg::Pair(register)]) => Instruction::Inc16(register),
+            (Mnemonic::Dec, &[Arg::Pair(register)]) => Instruction::Dec16(register),
+            (Mnemonic::Inc, &[arg]) => Instruction::Inc(operand(arg)),
+            (Mnemonic::Dec, &[arg]) => Instruction::Dec(operand(arg)),
+            (Mnemonic::Rlca, _) => Instruction::RotateA(Shift::Rlc),
+            (Mnemonic::Rrca, _) => Instruction::RotateA(Shift::Rrc),
+            (Mnemonic::Rla, _) => Instruction::RotateA(Shift::Rl),
+            (Mnemonic::Rra, _) => Instruction::RotateA(Shift::Rr),
+            (Mnemonic::Daa, _) => Instruction::Daa,
+            (Mnemonic::Cpl, _) => Instruction::Cpl,
+            (Mnemonic::Scf, _) => Instruction::Scf,
+            (Mnemonic::Ccf, _) => Instruction::Ccf,
+            (Mnemonic::Jp, &[Arg::Pair(_)]) => Instruction::JpHl,
+            (Mnemonic::Jp, &[Arg::Cond(condition), _]) => Instruction::Jp(condition, nn),
+            (Mnemonic::Jp, _) => Instruction::Jp(always, nn),
+            (Mnemonic::Jr, &[Arg::Cond(condition), _]) => Instruction::Jr(condition, e),
+            (Mnemonic::Jr, _) => Instruction::Jr(always, e),
+            (Mnemonic::Call, &[Arg::Cond(condition), _]) => Instruction::Call(condition, nn),
+            (Mnemonic::Call, _) => Instruction::Call(always, nn),
+            (Mnemonic::Ret, &[Arg::Cond(condition)]) => Instruction::Ret(condition),
+            (Mnemonic::Ret, _) => Instruction::Ret(always),
+            (Mnemonic::Reti, _) => Instruction::Reti,
+            (Mnemonic::Rst, &[Arg::Vector(vector)]) => Instruction::Rst(vector),
+            (Mnemonic::Bit, &[Arg::Bit(bit), arg]) => Instruction::Bit(bit, operand(arg)),
+            (Mnemonic::Res, &[Arg::Bit(bit), arg]) => Instruction::Res(bit, operand(arg)),
+            (Mnemonic::Set, &[Arg::Bit(bit), arg]) => Instruction::Set(bit, operand(arg)),
+            (Mnemonic::Illegal, _) => Instruction::Illegal(self.opcode),
+            (mnemonic, operands) => {
+                let arg = operands[operands.len() - 1];
+                match (alu(mnemonic), shift(mnemonic)) {
+                    (Some(op), _) => Instruction::Alu(op, operand(arg)),
+                    (_, Some(op)) => Instruction::Shift(op, operand(arg)),
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+    /// USAGE: row.disassemble(BYTES, ADDR) where BYTES holds the instruction found at ADDR
+    /// Returns the instruction in the usual assembler syntax, e.g. LD A,(HL+) or JP NZ,$0150
+    /// Relative jumps are written with the address they jump to
+    pub fn disassemble(&self, bytes: &[u8], address: u16) -> String {
+        let (n, nn) = immediates(bytes);
+        let signed = |e: i8| {
+            let sign = if e < 0 { '-' } else { '+' };
+            format!("{}${:02X}", sign, (e as i16).abs())
+        };
+        if self.mnemonic == Mnemonic::Illegal {
+            return format!(".db ${:02X}", self.opcode);
+        }
+        let operands: Vec<String> = self.operands
+            .iter()
+            .map(|arg| match *arg {
+                Arg::Reg(register) => format!("{:?}", register),
+                Arg::Pair(register) => format!("{:?}", register),
+                Arg::Ind(register) => format!("({:?})", register),
+                Arg::HlInc => String::from("(HL+)"),
+                Arg::HlDec => String::from("(HL-)"),
+                Arg::HighC => String::from("(C)"),
+                Arg::N8 => format!("${:02X}", n),
+                Arg::N16 => format!("${:04X}", nn),
+                Arg::A8 => format!("(${:02X})", n),
+                Arg::A16 => format!("(${:04X})", nn),
+                Arg::E8 if self.mnemonic == Mnemonic::Jr => {
+                    let target = address.wrapping_add(2).wrapping_add(n as i8 as u16);
+                    format!("${:04X}", target)
+                }
+                Arg::E8 => signed(n as i8),
+                Arg::SpE8 => format!("SP{}", signed(n as i8)),
+                Arg::Cond(condition) => format!("{:?}", condition),
+                Arg::Bit(bit) => format!("{}", bit),
+                Arg::Vector(vector) => format!("${:02X}", vector),
+            })
+            .collect();
+        let mnemonic = format!("{:?}", self.mnemonic).to_uppercase();
+        if operands.is_empty() {
+            mnemonic
+        } else {
+            format!("{} {}", mnemonic, operands.join(","))
+        }
+    }
+}
+
+/// Returns the immediate byte and word following the opcode in BYTES, zero where missing
+fn immediates(bytes: &[u8]) -> (u8, u16) {
+    let n = bytes.get(1).cloned().unwrap_or(0);
+    let high = bytes.get(2).cloned().unwrap_or(0);
+    (n, (high as u16) << 8 | n as u16)
+}
+
+/// Returns the accumulator operation MNEMONIC stands for, if any
+fn alu(mnemonic: Mnemonic) -> Option<Alu> {
+    match mnemonic {
+        Mnemonic::Add => Some(Alu::Add),
+        Mnemonic::Adc => Some(Alu::Adc),
+        Mnemonic::Sub => Some(Alu::Sub),
+        Mnemonic::Sbc => Some(Alu::Sbc),
+        Mnemonic::And => Some(Alu::And),
+        Mnemonic::Xor => Some(Alu::Xor),
+        Mnemonic::Or => Some(Alu::Or),
+        Mnemonic::Cp => Some(Alu::Cp),
+        _ => None,
+    }
+}
+
+/// Returns the CB-prefixed shift MNEMONIC stands for, if any
+fn shift(mnemonic: Mnemonic) -> Option<Shift> {
+    match mnemonic {
+        Mnemonic::Rlc => Some(Shift::Rlc),
+        Mnemonic::Rrc => Some(Shift::Rrc),
+        Mnemonic::Rl => Some(Shift::Rl),
+        Mnemonic::Rr => Some(Shift::Rr),
+        Mnemonic::Sla => Some(Shift::Sla),
+        Mnemonic::Sra => Some(Shift::Sra),
+        Mnemonic::Swap => Some(Shift::Swap),
+        Mnemonic::Srl => Some(Shift::Srl),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::CPU;
+
+    /// Runs the instruction in BYTES from 0x0100 with F set to F, returning the CPU afterwards
+    /// and the m-cycles the instruction took
+    fn run(bytes: &[u8], f: u16) -> (CPU<[u8; 0x10000]>, u8) {
+        let mut mem = [0u8; 0x10000];
+        mem[0x100..0x100 + bytes.len()].copy_from_slice(bytes);
+        let mut cpu = CPU::new(mem);
+        let mut regs = cpu.registers();
+        regs.pc = 0x100;
+        regs.sp = 0xD000;
+        regs.set_hl(0xC000);
+        regs.set_af(f);
+        cpu.set_registers(&regs);
+        let cycles = cpu.step().unwrap();
+        (cpu, cycles)
+    }
+
+    /// Returns every row that describes a real instruction along with bytes encoding it
+    fn instructions() -> Vec<(&'static Opcode, Vec<u8>)> {
+        let base = OPCODES
+            .iter()
+            .filter(|row| row.mnemonic != Mnemonic::Prefix && row.mnemonic != Mnemonic::Illegal)
+            .map(|row| (row, vec![row.opcode, 0x10, 0xC0]));
+        let cb = CB_OPCODES.iter().map(|row| (row, vec![0xCB, row.opcode]));
+        base.chain(cb).collect()
+    }
+
+    // Checks that each row sits at the index of its own opcode and is well formed
+    #[test]
+    fn opcode_table_is_in_order() {
+        for (i, row) in OPCODES.iter().chain(CB_OPCODES.iter()).enumerate() {
+            assert_eq!(row.opcode as usize, i % 0x100);
+            assert!((1..=3).contains(&row.length));
+            assert_eq!(row.flags.len(), 4);
+            for (flag, name) in row.flags.chars().zip("ZNHC".chars()) {
+                assert!(flag == name || "-01".contains(flag), "{:#04X}", row.opcode);
+            }
+        }
+        assert!(CB_OPCODES.iter().all(|row| row.length == 2));
+        let illegal = OPCODES.iter().filter(|row| row.mnemonic == Mnemonic::Illegal).count();
+        assert_eq!(illegal, 11);
+    }
+    // Checks the disassembly of a handful of opcodes, including ones the old disassembler got
+    // wrong or left out
+    #[test]
+    fn can_disassemble_instructions() {
+        let cases: [(&[u8], &str); 22] = [
+            (&[0x00], "NOP"),
+            (&[0x10, 0x00], "STOP"),
+            (&[0x08, 0x34, 0x12], "LD ($1234),SP"),
+            (&[0x0E, 0x42], "LD C,$42"),
+            (&[0x0F], "RRCA"),
+            (&[0x18, 0xFE], "JR $0150"),
+            (&[0x20, 0x05], "JR NZ,$0157"),
+            (&[0x2A], "LD A,(HL+)"),
+            (&[0x36, 0x99], "LD (HL),$99"),
+            (&[0x7E], "LD A,(HL)"),
+            (&[0x96], "SUB (HL)"),
+            (&[0xC6, 0x01], "ADD A,$01"),
+            (&[0xC4, 0x50, 0x01], "CALL NZ,$0150"),
+            (&[0xCB, 0x7C], "BIT 7,H"),
+            (&[0xCB, 0x37], "SWAP A"),
+            (&[0xE0, 0x40], "LDH ($40),A"),
+            (&[0xE8, 0x80], "ADD SP,-$80"),
+            (&[0xE9], "JP HL"),
+            (&[0xF1], "POP AF"),
+            (&[0xF8, 0x05], "LD HL,SP+$05"),
+            (&[0xFF], "RST $38"),
+            (&[0xD3], ".db $D3"),
+        ];
+        for &(bytes, text) in &cases {
+            let row = Opcode::lookup(bytes).unwrap();
+            assert_eq!(row.disassemble(bytes, 0x0150), text);
+        }
+    }
+    // Checks that slices too short for the whole instruction have no row
+    #[test]
+    fn cannot_look_up_truncated_instructions() {
+        assert!(Opcode::lookup(&[]).is_none());
+        for row in OPCODES.iter() {
+            let bytes = [row.opcode, 0x00, 0x00];
+            let length = row.length as usize;
+            assert!(Opcode::lookup(&bytes[..length - 1]).is_none());
+            assert!(Opcode::lookup(&bytes[..length]).is_some());
+        }
+    }
+    // Checks for every opcode in the table, with every condition both holding and failing,
+    // that the CPU advances PC by the listed length and takes the listed number of cycles
+    #[test]
+    fn opcode_table_timing_agrees_with_cpu() {
+        for (row, bytes) in instructions() {
+            for &f in &[0x00, 0xF0] {
+                let (cpu, cycles) = run(&bytes, f);
+                let text = row.disassemble(&bytes, 0x100);
+                if cpu.registers().pc == 0x100 + row.length as u16 {
+                    assert_eq!(cycles, row.cycles, "{}", text);
+                } else {
+                    assert_eq!(cycles, row.taken.unwrap_or(row.cycles), "{}", text);
+                }
+            }
+        }
+    }
+    // Checks for every opcode in the table that flags listed as unchanged, reset or set come
+    // out that way no matter what they were before
+    #[test]
+    fn opcode_table_flags_agree_with_cpu() {
+        for (row, bytes) in instructions() {
+            for &f in &[0x00, 0xF0] {
+                let (cpu, _) = run(&bytes, f);
+                let after = cpu.registers().f;
+                for (i, flag) in row.flags.chars().enumerate() {
+                    let mask = 0x80 >> i;
+                    let expected = match flag {
+                        '-' => f as u8 & mask,
+                        '0' => 0,
+                        '1' => mask,
+                        _ => continue,
+                    };
+                    let text = row.disassemble(&bytes, 0x100);
+                    assert_eq!(after & mask, expected, "{} flag {} F={:02X}", text, i, f);
+                }
+            }
+        }
+    }
+    // Checks that rows build the instructions the CPU executes
+    #[test]
+    fn can_build_instructions_from_rows() {
+        let cases: [(&[u8], Instruction); 6] = [
+            (&[0x01, 0x34, 0x12], Instruction::Ld16(R16::BC, 0x1234)),
+            (&[0x36, 0x99], Instruction::Ld(Operand::Ind(R16::HL), Operand::Reg(R8::CONST(0x99)))),
+            (&[0xFA, 0x00, 0xC0], Instruction::Ld(Operand::Reg(R8::A), Operand::Abs(0xC000))),
+            (&[0xD8], Instruction::Ret(Condition::C)),
+            (&[0xCB, 0x1E], Instruction::Shift(Shift::Rr, Operand::Ind(R16::HL))),
+            (&[0xE6, 0x0F], Instruction::Alu(Alu::And, Operand::Reg(R8::CONST(0x0F)))),
+        ];
+        for &(bytes, instruction) in &cases {
+            assert_eq!(Opcode::lookup(bytes).unwrap().instruction(bytes), instruction);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// M-cycles in one second of the clock, which runs off its own 32.768 KHz crystal
+pub const CYCLES_PER_SECOND: u32 = 1 << 20;
+
+/// Size of a saved clock, in the layout other emulators append to MBC3 saves
+/// Five live registers, then five latched ones, as 32-bit words, then a 64-bit UNIX time.
+pub const RTC_SAVE_SIZE: usize = 48;
+
+/// Register numbers, as written to 0x4000-0x5FFF to map them at 0xA000-0xBFFF
+pub const RTC_S: u8 = 0x08;
+pub const RTC_M: u8 = 0x09;
+pub const RTC_H: u8 = 0x0A;
+pub const RTC_DL: u8 = 0x0B;
+pub const RTC_DH: u8 = 0x0C;
+
+/// Bits of DH besides bit 8 of the day counter
+const HALT: u8 = 0x40;
+const CARRY: u8 = 0x80;
+
+/// The real-time clock of an MBC3
+/// The game reads a latched copy of the registers, taken when it writes 0x00 then 0x01 to
+/// 0x6000-0x7FFF, while writes go straight to the running clock.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    carry: bool,
+    latched: [u8; 5],
+    cycles: u32,
+}
+
+impl Rtc {
+    /// USAGE: Rtc::new()
+    /// Returns a clock at day 0, 00:00:00, and running
+    pub fn new() -> Self {
+        Rtc::default()
+    }
+    /// USAGE: rtc.registers()
+    /// Returns S, M, H, DL and DH as the running clock has them
+    pub fn registers(&self) -> [u8; 5] {
+        let mut dh = (self.days >> 8) as u8;
+        if self.halt {
+            dh |= HALT;
+        }
+        if self.carry {
+            dh |= CARRY;
+        }
+        [self.seconds, self.minutes, self.hours, self.days as u8, dh]
+    }
+    /// USAGE: rtc.latch()
+    /// Copies the running clock into the registers the game reads
+    pub fn latch(&mut self) {
+        self.latched = self.registers();
+    }
+    /// USAGE: rtc.read(REG) where REG is one of RTC_S to RTC_DH
+    /// Returns the latched value of REG
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - RTC_S) as usize]
+    }
+    /// USAGE: rtc.write(REG, N) where REG is one of RTC_S to RTC_DH
+    /// Sets REG on the running clock, keeping only the bits it has
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            RTC_S => {
+                self.seconds = value & 0x3F;
+                // Writing the seconds restarts the current second
+                self.cycles = 0;
+            }
+            RTC_M => self.minutes = value & 0x3F,
+            RTC_H => self.hours = value & 0x1F,
+            RTC_DL => self.days = self.days & 0x100 | u16::from(value),
+            _ => {
+                self.days = self.days & 0xFF | u16::from(value & 0x01) << 8;
+                self.halt = value & HALT != 0;
+                self.carry = value & CARRY != 0;
+            }
+        }
+    }
+    /// USAGE: rtc.tick()
+    /// Advances the clock by one m-cycle, unless it is halted
+    pub fn tick(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.cycles += 1;
+        if self.cycles == CYCLES_PER_SECOND {
+            self.cycles = 0;
+            self.advance(1);
+        }
+    }
+    /// USAGE: rtc.advance(SECONDS)
+    /// Advances the clock by SECONDS whole seconds, unless it is halted
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halt {
+            return;
+        }
+        // Out of range values count up to the top of their register before wrapping to 0,
+        // without carrying, which only stepping a second at a time gets right
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.step();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+        let total = u64::from(self.seconds)
+            + u64::from(self.minutes) * 60
+            + u64::from(self.hours) * 3600
+            + u64::from(self.days) * 86400
+            + seconds;
+        let days = total / 86400;
+        if days >= 512 {
+            self.carry = true;
+        }
+        self.days = (days % 512) as u16;
+        self.hours = (total % 86400 / 3600) as u8;
+        self.minutes = (total % 3600 / 60) as u8;
+        self.seconds = (total % 60) as u8;
+    }
+    /// Advances the clock by one second, carrying only out of a register that hits its limit
+    fn step(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days = (self.days + 1) & 0x1FF;
+        if self.days == 0 {
+            self.carry = true;
+        }
+    }
+    /// USAGE: rtc.save(TIME) where TIME is the UNIX time the save is made at, e.g. unix_time()
+    /// Returns the clock as RTC_SAVE_SIZE bytes, for the end of the save file
+    pub fn save(&self, time: u64) -> Vec<u8> {
+        let mut save = Vec::with_capacity(RTC_SAVE_SIZE);
+        for &register in self.registers().iter().chain(self.latched.iter()) {
+            save.extend_from_slice(&[register, 0, 0, 0]);
+        }
+        for i in 0..8 {
+            save.push((time >> (i * 8)) as u8);
+        }
+        save
+    }
+    /// USAGE: rtc.load(SAVE, NOW) where SAVE came from rtc.save()
+    /// Restores the clock from SAVE, or returns false if SAVE is not a saved clock
+    /// With NOW, a UNIX time, the seconds since the save are added on, as though the battery
+    /// had kept the clock running. With None, it picks up exactly where it was saved.
+    pub fn load(&mut self, save: &[u8], now: Option<u64>) -> bool {
+        if save.len() != RTC_SAVE_SIZE {
+            return false;
+        }
+        for (i, register) in (RTC_S..=RTC_DH).enumerate() {
+            self.write(register, save[i * 4]);
+            self.latched[i] = save[20 + i * 4];
+        }
+        let time = save[40..].iter().rev().fold(0, |time, &byte| time << 8 | u64::from(byte));
+        if let Some(now) = now {
+            self.advance(now.saturating_sub(time));
+        }
+        true
+    }
+}
+
+/// USAGE: rtc::unix_time()
+/// Returns the current UNIX time in seconds, to save and load clocks with
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Returns a clock set to DAYS, HOURS, MINUTES and SECONDS, with DH flags FLAGS
+    fn clock(days: u16, hours: u8, minutes: u8, seconds: u8, flags: u8) -> Rtc {
+        let mut rtc = Rtc::new();
+        rtc.write(RTC_S, seconds);
+        rtc.write(RTC_M, minutes);
+        rtc.write(RTC_H, hours);
+        rtc.write(RTC_DL, days as u8);
+        rtc.write(RTC_DH, (days >> 8) as u8 | flags);
+        rtc
+    }
+
+    // Checks that a second passes every 2^20 m-cycles, and only while not halted
+    #[test]
+    fn rtc_counts_emulated_cycles() {
+        let mut rtc = Rtc::new();
+        for _ in 0..CYCLES_PER_SECOND - 1 {
+            rtc.tick();
+        }
+        assert_eq!(rtc.registers(), [0, 0, 0, 0, 0]);
+        rtc.tick();
+        assert_eq!(rtc.registers(), [1, 0, 0, 0, 0]);
+        // Halting stops the clock, and writing the seconds restarts the second
+        rtc.write(RTC_DH, HALT);
+        for _ in 0..CYCLES_PER_SECOND {
+            rtc.tick();
+        }
+        rtc.advance(100);
+        assert_eq!(rtc.registers(), [1, 0, 0, 0, HALT]);
+        for _ in 0..CYCLES_PER_SECOND / 2 {
+            rtc.tick();
+        }
+        rtc.write(RTC_DH, 0);
+        rtc.write(RTC_S, 1);
+        for _ in 0..CYCLES_PER_SECOND - 1 {
+            rtc.tick();
+        }
+        assert_eq!(rtc.registers()[0], 1);
+    }
+    // Checks that reads only see the clock as of the last latch
+    #[test]
+    fn rtc_reads_latched_registers() {
+        let mut rtc = clock(0x1AB, 23, 59, 58, 0);
+        assert_eq!(rtc.read(RTC_S), 0);
+        rtc.latch();
+        rtc.advance(3);
+        let latched: Vec<u8> = (RTC_S..=RTC_DH).map(|register| rtc.read(register)).collect();
+        assert_eq!(latched, [58, 59, 23, 0xAB, 0x01]);
+        rtc.latch();
+        assert_eq!((rtc.read(RTC_S), rtc.read(RTC_DL), rtc.read(RTC_DH)), (1, 0xAC, 0x01));
+    }
+    // Checks that the day counter carries out of bit 8, and that the carry sticks
+    #[test]
+    fn rtc_carries_out_of_the_day_counter() {
+        let mut rtc = clock(511, 23, 59, 59, 0);
+        rtc.advance(1);
+        assert_eq!(rtc.registers(), [0, 0, 0, 0, CARRY]);
+        rtc.advance(86400 * 600 + 3661);
+        assert_eq!(rtc.registers(), [1, 1, 1, 88, CARRY]);
+        rtc.write(RTC_DH, 0);
+        assert_eq!(rtc.registers()[4], 0);
+        // Stepping a second at a time gets the same answer
+        let mut stepped = clock(511, 23, 59, 59, 0);
+        for _ in 0..86400 * 2 {
+            stepped.step();
+        }
+        let mut advanced = clock(511, 23, 59, 59, 0);
+        advanced.advance(86400 * 2);
+        assert_eq!(stepped, advanced);
+    }
+    // Checks that out of range values wrap at the size of their register, without carrying
+    #[test]
+    fn rtc_wraps_out_of_range_values() {
+        let mut rtc = clock(0, 31, 63, 62, 0);
+        rtc.advance(1);
+        assert_eq!(rtc.registers(), [63, 63, 31, 0, 0]);
+        rtc.advance(1);
+        assert_eq!(rtc.registers(), [0, 63, 31, 0, 0]);
+        rtc.advance(60);
+        assert_eq!(rtc.registers(), [0, 0, 31, 0, 0]);
+        rtc.advance(3600);
+        assert_eq!(rtc.registers(), [0, 0, 0, 0, 0]);
+        // Only the bits each register has are kept
+        let rtc = clock(0x1FF, 0xFF, 0xFF, 0xFF, 0x3E);
+        assert_eq!(rtc.registers(), [0x3F, 0x3F, 0x1F, 0xFF, 0x01]);
+    }
+    // Checks that a saved clock comes back as it was, or as it would be now
+    #[test]
+    fn rtc_can_save_and_sync_to_wall_clock() {
+        let mut rtc = clock(2, 3, 4, 5, 0);
+        rtc.latch();
+        rtc.advance(10);
+        let save = rtc.save(1_000_000);
+        assert_eq!(save.len(), RTC_SAVE_SIZE);
+        assert_eq!((save[0], save[20], save[40], save[41], save[42]), (15, 5, 0x40, 0x42, 0x0F));
+        let mut loaded = Rtc::new();
+        assert!(loaded.load(&save, None));
+        assert_eq!((loaded.registers(), loaded.read(RTC_S)), (rtc.registers(), 5));
+        assert!(loaded.load(&save, Some(1_000_000 + 86400 + 45)));
+        assert_eq!(loaded.registers(), [0, 5, 3, 3, 0]);
+        // A halted clock stays put, and a clock from the future does not go backwards
+        let mut halted = clock(2, 3, 4, 5, HALT);
+        let save = halted.save(1_000_000);
+        assert!(halted.load(&save, Some(2_000_000)));
+        assert_eq!(halted.registers(), [5, 4, 3, 2, HALT]);
+        assert!(loaded.load(&rtc.save(1_000_000), Some(0)));
+        assert_eq!(loaded.registers(), rtc.registers());
+        assert!(!loaded.load(&save[..40], None));
+        assert!(unix_time() > 1_500_000_000);
+    }
+}